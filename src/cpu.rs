@@ -0,0 +1,841 @@
+use crate::instruction_decode::{
+    DecodeError, Decoder, ExplicitSize, FieldEncoding, Instruction, Opcode, Operand, Prefixes,
+    RawData, Register, Repeat,
+};
+
+use crate::flags;
+pub use crate::flags::{AF, CF, DF, IF, OF, PF, SF, TF, ZF};
+use crate::memory::{Memory, MEMORY_SIZE};
+
+// the longest 8086 instruction, prefixes aside
+const FETCH_LENGTH: usize = 6;
+
+// whatever sits behind the i/o ports, `in` reads from it and `out` writes to it
+pub trait PortHandler {
+    fn read(&mut self, port: u16, word: bool) -> u16;
+    fn write(&mut self, port: u16, value: u16, word: bool);
+}
+
+// nothing connected, reads see the floating bus as all ones
+pub struct NoPorts;
+
+impl PortHandler for NoPorts {
+    fn read(&mut self, _port: u16, word: bool) -> u16 {
+        match word {
+            true => 0xFFFF,
+            false => 0xFF,
+        }
+    }
+
+    fn write(&mut self, _port: u16, _value: u16, _word: bool) {}
+}
+
+// why an instruction couldn't be run
+#[derive(Debug)]
+pub enum ExecError {
+    Decode(DecodeError),
+    // decoded fine, but there's no execution for it
+    Unsupported(Instruction),
+}
+
+impl std::fmt::Display for ExecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecError::Decode(err) => write!(f, "{}", err),
+            ExecError::Unsupported(instruction) => write!(
+                f,
+                "can't execute {} at offset {}",
+                instruction, instruction.address
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ExecError {}
+
+impl From<DecodeError> for ExecError {
+    fn from(err: DecodeError) -> Self {
+        ExecError::Decode(err)
+    }
+}
+
+pub struct Cpu {
+    pub ax: u16,
+    pub bx: u16,
+    pub cx: u16,
+    pub dx: u16,
+    pub sp: u16,
+    pub bp: u16,
+    pub si: u16,
+    pub di: u16,
+    pub es: u16,
+    pub cs: u16,
+    pub ss: u16,
+    pub ds: u16,
+    pub ip: u16,
+    pub flags: u16,
+    // set by hlt, run stops there
+    pub halted: bool,
+    // physical addresses the program was loaded to, run stops when cs:ip leaves them
+    // the end is past MEMORY_SIZE when the program wrapped around to 0
+    program: std::ops::Range<usize>,
+    pub memory: Memory,
+    pub ports: Box<dyn PortHandler>,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Cpu {
+    pub fn new() -> Self {
+        Cpu {
+            ax: 0,
+            bx: 0,
+            cx: 0,
+            dx: 0,
+            sp: 0,
+            bp: 0,
+            si: 0,
+            di: 0,
+            es: 0,
+            cs: 0,
+            ss: 0,
+            ds: 0,
+            ip: 0,
+            flags: 0,
+            halted: false,
+            program: 0..0,
+            memory: Memory::new(),
+            ports: Box::new(NoPorts),
+        }
+    }
+
+    pub fn get_register(&self, register: &Register) -> u16 {
+        match register {
+            Register::AX => self.ax,
+            Register::AL => self.ax & 0xFF,
+            Register::AH => self.ax >> 8,
+            Register::BX => self.bx,
+            Register::BL => self.bx & 0xFF,
+            Register::BH => self.bx >> 8,
+            Register::CX => self.cx,
+            Register::CL => self.cx & 0xFF,
+            Register::CH => self.cx >> 8,
+            Register::DX => self.dx,
+            Register::DL => self.dx & 0xFF,
+            Register::DH => self.dx >> 8,
+            Register::SP => self.sp,
+            Register::BP => self.bp,
+            Register::SI => self.si,
+            Register::DI => self.di,
+            Register::ES => self.es,
+            Register::CS => self.cs,
+            Register::SS => self.ss,
+            Register::DS => self.ds,
+        }
+    }
+
+    pub fn set_register(&mut self, register: &Register, value: u16) {
+        // the 8 bit halves only replace their own byte of the 16 bit register
+        let low = |reg: u16| (reg & 0xFF00) | (value & 0xFF);
+        let high = |reg: u16| (reg & 0x00FF) | (value << 8);
+        match register {
+            Register::AX => self.ax = value,
+            Register::AL => self.ax = low(self.ax),
+            Register::AH => self.ax = high(self.ax),
+            Register::BX => self.bx = value,
+            Register::BL => self.bx = low(self.bx),
+            Register::BH => self.bx = high(self.bx),
+            Register::CX => self.cx = value,
+            Register::CL => self.cx = low(self.cx),
+            Register::CH => self.cx = high(self.cx),
+            Register::DX => self.dx = value,
+            Register::DL => self.dx = low(self.dx),
+            Register::DH => self.dx = high(self.dx),
+            Register::SP => self.sp = value,
+            Register::BP => self.bp = value,
+            Register::SI => self.si = value,
+            Register::DI => self.di = value,
+            Register::ES => self.es = value,
+            Register::CS => self.cs = value,
+            Register::SS => self.ss = value,
+            Register::DS => self.ds = value,
+        }
+    }
+
+    pub fn get_flag(&self, flag: u16) -> bool {
+        self.flags & flag != 0
+    }
+
+    pub fn set_flag(&mut self, flag: u16, value: bool) {
+        match value {
+            true => self.flags |= flag,
+            false => self.flags &= !flag,
+        }
+    }
+
+    pub fn read_byte(&self, segment: u16, offset: u16) -> u8 {
+        self.memory.read_byte(segment, offset)
+    }
+
+    pub fn write_byte(&mut self, segment: u16, offset: u16, value: u8) {
+        self.memory.write_byte(segment, offset, value)
+    }
+
+    pub fn read_word(&self, segment: u16, offset: u16) -> u16 {
+        self.memory.read_word(segment, offset)
+    }
+
+    pub fn write_word(&mut self, segment: u16, offset: u16, value: u16) {
+        self.memory.write_word(segment, offset, value)
+    }
+
+    // puts the program at segment:offset and points cs:ip at its first byte
+    pub fn load(&mut self, image: &[u8], segment: u16, offset: u16) {
+        self.program = self.memory.load(image, segment, offset);
+        self.cs = segment;
+        self.ip = offset;
+        self.halted = false;
+    }
+
+    // not halted and cs:ip still inside the loaded program
+    pub fn is_running(&self) -> bool {
+        // counted from the start of the program, over the top of memory and on from 0
+        let offset = Memory::physical_address(self.cs, self.ip).wrapping_sub(self.program.start)
+            & (MEMORY_SIZE - 1);
+        !self.halted && offset < self.program.len()
+    }
+
+    // decodes the instruction at cs:ip, moves ip past it and executes it
+    // the instruction's address is its ip, so jump targets print as the ip they go to
+    // ip stays on an instruction that can't be executed
+    pub fn step(&mut self) -> Result<Instruction, ExecError> {
+        let (instruction, length) = self.fetch()?;
+        let ip = self.ip;
+        self.ip = self.ip.wrapping_add(length as u16);
+        if let Err(err) = self.execute(&instruction) {
+            self.ip = ip;
+            return Err(err);
+        }
+        Ok(instruction)
+    }
+
+    // decodes the instruction at cs:ip reading through the segment, it wraps at offset ffff and at
+    // the top of memory like any other read, offsets in it and in errors are relative to cs
+    fn fetch(&self) -> Result<(Instruction, usize), DecodeError> {
+        let mut bytes: Vec<u8> = Vec::with_capacity(FETCH_LENGTH);
+        loop {
+            // only prefixes make it longer, fetch more when they've used the bytes up
+            let start = bytes.len();
+            bytes.extend(
+                (start..start + FETCH_LENGTH)
+                    .map(|n| self.read_byte(self.cs, self.ip.wrapping_add(n as u16))),
+            );
+            let ip = |offset: usize| self.ip.wrapping_add(offset as u16) as usize;
+            return match Decoder::decode_at(&bytes, 0) {
+                Err(DecodeError::TruncatedInstruction { .. }) if bytes.len() <= 0xFFFF => continue,
+                Ok((mut instruction, length)) => {
+                    instruction.address = self.ip as usize;
+                    Ok((instruction, length))
+                }
+                Err(DecodeError::UnknownOpcode { offset, byte }) => {
+                    Err(DecodeError::UnknownOpcode {
+                        offset: ip(offset),
+                        byte,
+                    })
+                }
+                Err(DecodeError::TruncatedInstruction { offset }) => {
+                    Err(DecodeError::TruncatedInstruction { offset: ip(offset) })
+                }
+                Err(err) => Err(err),
+            };
+        }
+    }
+
+    pub fn run(&mut self) -> Result<(), ExecError> {
+        while self.is_running() {
+            self.step()?;
+        }
+        Ok(())
+    }
+
+    pub fn dump_registers(&self) {
+        let registers = [
+            ("ax", self.ax),
+            ("bx", self.bx),
+            ("cx", self.cx),
+            ("dx", self.dx),
+            ("sp", self.sp),
+            ("bp", self.bp),
+            ("si", self.si),
+            ("di", self.di),
+            ("es", self.es),
+            ("cs", self.cs),
+            ("ss", self.ss),
+            ("ds", self.ds),
+            ("ip", self.ip),
+        ];
+        for (name, value) in registers {
+            if value != 0 {
+                println!("{:>8}: 0x{:04x} ({})", name, value, value);
+            }
+        }
+        let flags: String = [
+            (CF, 'C'),
+            (PF, 'P'),
+            (AF, 'A'),
+            (ZF, 'Z'),
+            (SF, 'S'),
+            (TF, 'T'),
+            (IF, 'I'),
+            (DF, 'D'),
+            (OF, 'O'),
+        ]
+        .iter()
+        .filter(|(flag, _)| self.get_flag(*flag))
+        .map(|(_, name)| *name)
+        .collect();
+        println!("{:>8}: {}", "flags", flags);
+    }
+
+    // ip has to be past the instruction already, relative jumps count from there
+    pub fn execute(&mut self, instruction: &Instruction) -> Result<(), ExecError> {
+        match instruction.opcode {
+            Opcode::NOP => (),
+            Opcode::MOV => {
+                let value = self.read_operand(&instruction.operands[1], instruction)?;
+                self.write_operand(&instruction.operands[0], instruction, value)?;
+            }
+            // only the address is computed, memory isn't touched
+            Opcode::LEA => {
+                if let Operand::FieldEncoding(field, _) = &instruction.operands[1] {
+                    if let Some((_, offset)) = self.effective_address(field, &instruction.prefixes)
+                    {
+                        self.write_operand(&instruction.operands[0], instruction, offset)?;
+                    }
+                }
+            }
+            // the 8086 pushes sp as it is after the decrement
+            Opcode::PUSH => {
+                let value = match &instruction.operands[0] {
+                    Operand::FieldEncoding(FieldEncoding::Reg(Register::SP), _) => {
+                        self.sp.wrapping_sub(2)
+                    }
+                    operand => self.read_operand(operand, instruction)?,
+                };
+                self.push(value);
+            }
+            Opcode::POP => {
+                let value = self.pop();
+                self.write_operand(&instruction.operands[0], instruction, value)?;
+            }
+            Opcode::PUSHF => self.push(flags::to_word(self.flags)),
+            Opcode::POPF => self.flags = flags::from_word(self.pop()),
+            Opcode::ADD
+            | Opcode::ADC
+            | Opcode::SUB
+            | Opcode::SBB
+            | Opcode::CMP
+            | Opcode::AND
+            | Opcode::OR
+            | Opcode::XOR
+            | Opcode::TEST
+            | Opcode::NEG
+            | Opcode::NOT
+            | Opcode::INC
+            | Opcode::DEC => self.execute_arithmetic(instruction)?,
+            Opcode::JO
+            | Opcode::JNO
+            | Opcode::JB
+            | Opcode::JNB
+            | Opcode::JE
+            | Opcode::JNE
+            | Opcode::JBE
+            | Opcode::JA
+            | Opcode::JS
+            | Opcode::JNS
+            | Opcode::JP
+            | Opcode::JNP
+            | Opcode::JL
+            | Opcode::JNL
+            | Opcode::JLE
+            | Opcode::JG
+            | Opcode::LOOP
+            | Opcode::LOOPZ
+            | Opcode::LOOPNZ
+            | Opcode::JCXZ => self.execute_jump(instruction),
+            Opcode::JMP if matches!(instruction.operands[0], Operand::Relative(_)) => {
+                self.execute_jump(instruction)
+            }
+            Opcode::HLT => self.halted = true,
+            Opcode::CLC => self.set_flag(CF, false),
+            Opcode::STC => self.set_flag(CF, true),
+            Opcode::CMC => self.set_flag(CF, !self.get_flag(CF)),
+            Opcode::CLD => self.set_flag(DF, false),
+            Opcode::STD => self.set_flag(DF, true),
+            Opcode::CLI => self.set_flag(IF, false),
+            Opcode::STI => self.set_flag(IF, true),
+            Opcode::MOVSB
+            | Opcode::MOVSW
+            | Opcode::CMPSB
+            | Opcode::CMPSW
+            | Opcode::SCASB
+            | Opcode::SCASW
+            | Opcode::LODSB
+            | Opcode::LODSW
+            | Opcode::STOSB
+            | Opcode::STOSW => self.execute_string(instruction),
+            Opcode::IN | Opcode::OUT => self.execute_port(instruction),
+            Opcode::ROL
+            | Opcode::ROR
+            | Opcode::RCL
+            | Opcode::RCR
+            | Opcode::SHL
+            | Opcode::SHR
+            | Opcode::SAR => self.execute_shift(instruction)?,
+            Opcode::AAA | Opcode::AAS | Opcode::AAM | Opcode::AAD | Opcode::DAA | Opcode::DAS => {
+                self.execute_adjust(instruction)?
+            }
+            // sign extension, no flags change
+            Opcode::CBW => self.ax = self.ax as u8 as i8 as i16 as u16,
+            Opcode::CWD => self.dx = ((self.ax as i16) >> 15) as u16,
+            _ => return Err(ExecError::Unsupported(instruction.clone())),
+        }
+        Ok(())
+    }
+
+    // segment and offset of a memory operand from the registers as they are now, none for a register
+    // bp based addressing defaults to ss and everything else to ds, unless there's a segment override
+    pub fn effective_address(
+        &self,
+        field: &FieldEncoding,
+        prefixes: &Prefixes,
+    ) -> Option<(u16, u16)> {
+        let offset = match field {
+            FieldEncoding::Reg(_) => return None,
+            FieldEncoding::Direct(address) => *address,
+            FieldEncoding::Indexed(base, index, disp) => {
+                let index = index.as_ref().map_or(0, |index| self.get_register(index));
+                self.get_register(base)
+                    .wrapping_add(index)
+                    .wrapping_add(disp.unwrap_or(0) as u16)
+            }
+        };
+        let segment = match (&prefixes.segment, field) {
+            (Some(segment), _) => self.get_register(segment),
+            (None, FieldEncoding::Indexed(Register::BP, _, _)) => self.ss,
+            _ => self.ds,
+        };
+        Some((segment, offset))
+    }
+
+    fn read_operand(&self, operand: &Operand, instruction: &Instruction) -> Result<u16, ExecError> {
+        let value = match operand {
+            Operand::FieldEncoding(FieldEncoding::Reg(register), _) => self.get_register(register),
+            Operand::FieldEncoding(field, _) => {
+                let (segment, offset) = self
+                    .effective_address(field, &instruction.prefixes)
+                    .unwrap();
+                match Cpu::instruction_is_word(instruction) {
+                    true => self.read_word(segment, offset),
+                    false => self.read_byte(segment, offset) as u16,
+                }
+            }
+            Operand::RawData(RawData::U8(value), _) => *value as u16,
+            Operand::RawData(RawData::U16(value), _) => *value,
+            Operand::RawData(RawData::I8(value), _) => *value as i16 as u16,
+            Operand::RawData(RawData::I16(value), _) => *value as u16,
+            _ => return Err(ExecError::Unsupported(instruction.clone())),
+        };
+        Ok(value)
+    }
+
+    fn write_operand(
+        &mut self,
+        operand: &Operand,
+        instruction: &Instruction,
+        value: u16,
+    ) -> Result<(), ExecError> {
+        match operand {
+            Operand::FieldEncoding(FieldEncoding::Reg(register), _) => {
+                self.set_register(register, value)
+            }
+            Operand::FieldEncoding(field, _) => {
+                let (segment, offset) = self
+                    .effective_address(field, &instruction.prefixes)
+                    .unwrap();
+                match Cpu::instruction_is_word(instruction) {
+                    true => self.write_word(segment, offset, value),
+                    false => self.write_byte(segment, offset, value as u8),
+                }
+            }
+            _ => return Err(ExecError::Unsupported(instruction.clone())),
+        }
+        Ok(())
+    }
+
+    // a register or an explicit size decides, an immediate only when neither is there
+    fn instruction_is_word(instruction: &Instruction) -> bool {
+        instruction
+            .operands
+            .iter()
+            .find_map(|operand| match operand {
+                Operand::FieldEncoding(FieldEncoding::Reg(register), _) => Some(register.is_word()),
+                Operand::FieldEncoding(_, Some(size)) => Some(*size != ExplicitSize::Byte),
+                _ => None,
+            })
+            .unwrap_or_else(|| {
+                instruction.operands.iter().any(|operand| {
+                    matches!(
+                        operand,
+                        Operand::RawData(RawData::U16(_) | RawData::I16(_), _)
+                    )
+                })
+            })
+    }
+
+    fn execute_arithmetic(&mut self, instruction: &Instruction) -> Result<(), ExecError> {
+        let destination = &instruction.operands[0];
+        let word = Cpu::instruction_is_word(instruction);
+        let a = self.read_operand(destination, instruction)?;
+        // neg, not, inc and dec only have the one operand
+        let b = match instruction.operands.get(1) {
+            Some(source) => self.read_operand(source, instruction)?,
+            None => 0,
+        };
+        let (result, flags) = flags::alu(instruction.opcode, self.flags, a, b, word)
+            .ok_or_else(|| ExecError::Unsupported(instruction.clone()))?;
+        self.flags = flags;
+        // cmp and test only set the flags
+        if !matches!(instruction.opcode, Opcode::CMP | Opcode::TEST) {
+            self.write_operand(destination, instruction, result)?;
+        }
+        Ok(())
+    }
+
+    fn execute_jump(&mut self, instruction: &Instruction) {
+        let (of, cf, zf, sf, pf) = (
+            self.get_flag(OF),
+            self.get_flag(CF),
+            self.get_flag(ZF),
+            self.get_flag(SF),
+            self.get_flag(PF),
+        );
+        // the loops count cx down before testing it
+        if let Opcode::LOOP | Opcode::LOOPZ | Opcode::LOOPNZ = instruction.opcode {
+            self.cx = self.cx.wrapping_sub(1);
+        }
+        let taken = match instruction.opcode {
+            Opcode::JO => of,
+            Opcode::JNO => !of,
+            Opcode::JB => cf,
+            Opcode::JNB => !cf,
+            Opcode::JE => zf,
+            Opcode::JNE => !zf,
+            Opcode::JBE => cf || zf,
+            Opcode::JA => !cf && !zf,
+            Opcode::JS => sf,
+            Opcode::JNS => !sf,
+            Opcode::JP => pf,
+            Opcode::JNP => !pf,
+            Opcode::JL => sf != of,
+            Opcode::JNL => sf == of,
+            Opcode::JLE => zf || sf != of,
+            Opcode::JG => !zf && sf == of,
+            Opcode::LOOP => self.cx != 0,
+            Opcode::LOOPZ => self.cx != 0 && zf,
+            Opcode::LOOPNZ => self.cx != 0 && !zf,
+            Opcode::JCXZ => self.cx == 0,
+            _ => true,
+        };
+        if let (true, Some(Operand::Relative(disp))) = (taken, instruction.operands.first()) {
+            self.ip = self.ip.wrapping_add(*disp as u16);
+        }
+    }
+
+    // the 8086 doesn't mask the count, it really shifts up to 255 times
+    fn execute_shift(&mut self, instruction: &Instruction) -> Result<(), ExecError> {
+        let destination = &instruction.operands[0];
+        let count = self.read_operand(&instruction.operands[1], instruction)? & 0xFF;
+        if count == 0 {
+            return Ok(());
+        }
+        let word = Cpu::instruction_is_word(instruction);
+        let sign: u16 = match word {
+            true => 0x8000,
+            false => 0x80,
+        };
+        let mask = sign | (sign - 1);
+        let mut value = self.read_operand(destination, instruction)? & mask;
+        let mut carry = self.get_flag(CF);
+        let mut overflow = false;
+
+        for _ in 0..count {
+            let msb = value & sign != 0;
+            let lsb = value & 1 != 0;
+            value = match instruction.opcode {
+                Opcode::ROL => (value << 1) | (msb as u16),
+                Opcode::ROR => (value >> 1) | ((lsb as u16) * sign),
+                Opcode::RCL => (value << 1) | (carry as u16),
+                Opcode::RCR => (value >> 1) | ((carry as u16) * sign),
+                Opcode::SHL => value << 1,
+                Opcode::SHR => value >> 1,
+                _ => (value >> 1) | (value & sign),
+            } & mask;
+            carry = match instruction.opcode {
+                Opcode::ROL | Opcode::RCL | Opcode::SHL => msb,
+                _ => lsb,
+            };
+            let result_msb = value & sign != 0;
+            overflow = match instruction.opcode {
+                Opcode::ROL | Opcode::RCL | Opcode::SHL => result_msb != carry,
+                Opcode::ROR | Opcode::RCR => result_msb != (value & (sign >> 1) != 0),
+                Opcode::SHR => msb,
+                _ => false,
+            };
+        }
+
+        self.write_operand(destination, instruction, value)?;
+        self.set_flag(CF, carry);
+        self.set_flag(OF, overflow);
+        // rotates leave the other flags alone
+        if let Opcode::SHL | Opcode::SHR | Opcode::SAR = instruction.opcode {
+            self.set_result_flags(value, word);
+        }
+        Ok(())
+    }
+
+    // the flags intel leaves undefined are what the 8086 actually produces, they fall out of the
+    // internal add or sub the adjustment is done with
+    fn execute_adjust(&mut self, instruction: &Instruction) -> Result<(), ExecError> {
+        let al = self.get_register(&Register::AL);
+        let ah = self.get_register(&Register::AH);
+        let low_nibble_overflow = (al & 0xF) > 9 || self.get_flag(AF);
+        match instruction.opcode {
+            Opcode::AAA | Opcode::AAS => {
+                let (al, ah) = match (&instruction.opcode, low_nibble_overflow) {
+                    (Opcode::AAA, true) => {
+                        self.set_add_flags(al, 6, false);
+                        (al.wrapping_add(6), ah.wrapping_add(1))
+                    }
+                    (Opcode::AAA, false) => {
+                        self.set_add_flags(al, 0, false);
+                        (al, ah)
+                    }
+                    (_, true) => {
+                        self.set_sub_flags(al, 6, false);
+                        (al.wrapping_sub(6), ah.wrapping_sub(1))
+                    }
+                    (_, false) => {
+                        self.set_sub_flags(al, 0, false);
+                        (al, ah)
+                    }
+                };
+                self.set_flag(AF, low_nibble_overflow);
+                self.set_flag(CF, low_nibble_overflow);
+                self.set_register(&Register::AL, al & 0x0F);
+                self.set_register(&Register::AH, ah);
+            }
+            Opcode::DAA | Opcode::DAS => {
+                let high_overflow = al > 0x99 || self.get_flag(CF);
+                // taking the 6 off a low digit below 6 borrows out of al, das sets CF for that too
+                let borrow = instruction.opcode == Opcode::DAS && low_nibble_overflow && al < 6;
+                let adjustment = match (low_nibble_overflow, high_overflow) {
+                    (true, true) => 0x66,
+                    (true, false) => 0x06,
+                    (false, true) => 0x60,
+                    (false, false) => 0x00,
+                };
+                let result = match instruction.opcode {
+                    Opcode::DAA => {
+                        self.set_add_flags(al, adjustment, false);
+                        al.wrapping_add(adjustment)
+                    }
+                    _ => {
+                        self.set_sub_flags(al, adjustment, false);
+                        al.wrapping_sub(adjustment)
+                    }
+                };
+                self.set_flag(AF, low_nibble_overflow);
+                self.set_flag(CF, high_overflow || borrow);
+                self.set_register(&Register::AL, result);
+            }
+            Opcode::AAM => {
+                let base = self.read_operand(&instruction.operands[0], instruction)? & 0xFF;
+                if base == 0 {
+                    // divide error
+                    self.interrupt(0);
+                    return Ok(());
+                }
+                self.set_register(&Register::AH, (al & 0xFF) / base);
+                self.set_register(&Register::AL, (al & 0xFF) % base);
+                let result = self.get_register(&Register::AL);
+                self.set_result_flags(result, false);
+                self.set_flag(CF, false);
+                self.set_flag(AF, false);
+                self.set_flag(OF, false);
+            }
+            _ => {
+                let base = self.read_operand(&instruction.operands[0], instruction)? & 0xFF;
+                let product = (ah * base) & 0xFF;
+                self.set_add_flags(al, product, false);
+                self.set_register(&Register::AX, (al + product) & 0xFF);
+            }
+        }
+        Ok(())
+    }
+
+    // pushes flags, cs and ip and continues at the handler in the vector table
+    pub fn interrupt(&mut self, vector: u8) {
+        self.push(flags::to_word(self.flags));
+        self.push(self.cs);
+        self.push(self.ip);
+        self.set_flag(IF, false);
+        self.set_flag(TF, false);
+        let entry = vector as u16 * 4;
+        self.ip = self.read_word(0, entry);
+        self.cs = self.read_word(0, entry + 2);
+    }
+
+    pub fn push(&mut self, value: u16) {
+        self.sp = self.sp.wrapping_sub(2);
+        self.write_word(self.ss, self.sp, value);
+    }
+
+    pub fn pop(&mut self) -> u16 {
+        let value = self.read_word(self.ss, self.sp);
+        self.sp = self.sp.wrapping_add(2);
+        value
+    }
+
+    fn execute_port(&mut self, instruction: &Instruction) {
+        let mut port: u16 = 0;
+        let mut accumulator = &Register::AL;
+        for operand in instruction.operands.iter() {
+            match operand {
+                Operand::RawData(RawData::U8(value), _) => port = *value as u16,
+                Operand::FieldEncoding(FieldEncoding::Reg(Register::DX), _) => port = self.dx,
+                Operand::FieldEncoding(FieldEncoding::Reg(register), _) => accumulator = register,
+                _ => (),
+            }
+        }
+        let word = *accumulator == Register::AX;
+        match instruction.opcode {
+            Opcode::IN => {
+                let value = self.ports.read(port, word);
+                self.set_register(accumulator, value);
+            }
+            _ => {
+                let value = self.get_register(accumulator);
+                self.ports.write(port, value, word);
+            }
+        }
+    }
+
+    fn execute_string(&mut self, instruction: &Instruction) {
+        match &instruction.prefixes.repeat {
+            None => self.execute_string_step(instruction),
+            Some(repeat) => {
+                while self.cx != 0 {
+                    self.execute_string_step(instruction);
+                    self.cx = self.cx.wrapping_sub(1);
+                    // only cmps and scas look at the zero flag, rep acts as repe for them
+                    let compares = matches!(
+                        instruction.opcode,
+                        Opcode::CMPSB | Opcode::CMPSW | Opcode::SCASB | Opcode::SCASW
+                    );
+                    match repeat {
+                        Repeat::Rep if compares && !self.get_flag(ZF) => break,
+                        Repeat::Repne if compares && self.get_flag(ZF) => break,
+                        _ => (),
+                    }
+                }
+            }
+        }
+    }
+
+    fn execute_string_step(&mut self, instruction: &Instruction) {
+        let word = matches!(
+            instruction.opcode,
+            Opcode::MOVSW | Opcode::CMPSW | Opcode::SCASW | Opcode::LODSW | Opcode::STOSW
+        );
+        // the source can take a segment override, the destination is always es:di
+        let source_segment = match &instruction.prefixes.segment {
+            Some(segment) => self.get_register(segment),
+            None => self.ds,
+        };
+        let read = |cpu: &Cpu, segment: u16, offset: u16| match word {
+            true => cpu.read_word(segment, offset),
+            false => cpu.read_byte(segment, offset) as u16,
+        };
+        let accumulator = match word {
+            true => Register::AX,
+            false => Register::AL,
+        };
+
+        let (advance_si, advance_di) = match instruction.opcode {
+            Opcode::MOVSB | Opcode::MOVSW => {
+                let value = read(self, source_segment, self.si);
+                match word {
+                    true => self.write_word(self.es, self.di, value),
+                    false => self.write_byte(self.es, self.di, value as u8),
+                }
+                (true, true)
+            }
+            Opcode::CMPSB | Opcode::CMPSW => {
+                let source = read(self, source_segment, self.si);
+                let destination = read(self, self.es, self.di);
+                self.set_sub_flags(source, destination, word);
+                (true, true)
+            }
+            Opcode::SCASB | Opcode::SCASW => {
+                let destination = read(self, self.es, self.di);
+                self.set_sub_flags(self.get_register(&accumulator), destination, word);
+                (false, true)
+            }
+            Opcode::LODSB | Opcode::LODSW => {
+                let value = read(self, source_segment, self.si);
+                self.set_register(&accumulator, value);
+                (true, false)
+            }
+            Opcode::STOSB | Opcode::STOSW => {
+                let value = self.get_register(&accumulator);
+                match word {
+                    true => self.write_word(self.es, self.di, value),
+                    false => self.write_byte(self.es, self.di, value as u8),
+                }
+                (false, true)
+            }
+            _ => (false, false),
+        };
+
+        let step: u16 = match (word, self.get_flag(DF)) {
+            (true, false) => 2,
+            (false, false) => 1,
+            (true, true) => 2u16.wrapping_neg(),
+            (false, true) => 1u16.wrapping_neg(),
+        };
+        if advance_si {
+            self.si = self.si.wrapping_add(step);
+        }
+        if advance_di {
+            self.di = self.di.wrapping_add(step);
+        }
+    }
+
+    fn set_result_flags(&mut self, result: u16, word: bool) {
+        self.flags = flags::result(self.flags, result, word);
+    }
+
+    fn set_add_flags(&mut self, a: u16, b: u16, word: bool) {
+        self.flags = flags::add(self.flags, a, b, false, word).1;
+    }
+
+    // flags of a - b, as set by cmp
+    fn set_sub_flags(&mut self, a: u16, b: u16, word: bool) {
+        self.flags = flags::sub(self.flags, a, b, false, word).1;
+    }
+}
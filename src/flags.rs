@@ -0,0 +1,124 @@
+// the 8086 status flags and how arithmetic and logic results set them
+use crate::instruction_decode::Opcode;
+
+pub const CF: u16 = 1 << 0;
+pub const PF: u16 = 1 << 2;
+pub const AF: u16 = 1 << 4;
+pub const ZF: u16 = 1 << 6;
+pub const SF: u16 = 1 << 7;
+pub const TF: u16 = 1 << 8;
+pub const IF: u16 = 1 << 9;
+pub const DF: u16 = 1 << 10;
+pub const OF: u16 = 1 << 11;
+
+const DEFINED: u16 = CF | PF | AF | ZF | SF | TF | IF | DF | OF;
+// bits 1 and 12-15 aren't flags, but the 8086 always reads them as 1
+const ALWAYS_SET: u16 = 0xF002;
+
+// the flags as a word on the stack, the way pushf and interrupts push them
+pub fn to_word(flags: u16) -> u16 {
+    (flags & DEFINED) | ALWAYS_SET
+}
+
+// a word popf pops, only the bits that are flags are kept
+pub fn from_word(word: u16) -> u16 {
+    word & DEFINED
+}
+
+fn set(flags: u16, flag: u16, value: bool) -> u16 {
+    match value {
+        true => flags | flag,
+        false => flags & !flag,
+    }
+}
+
+// mask and sign bit of a byte or word
+fn width(word: bool) -> (u32, u32) {
+    match word {
+        true => (0xFFFF, 0x8000),
+        false => (0xFF, 0x80),
+    }
+}
+
+// sign, zero and parity of a byte or word result
+pub fn result(flags: u16, result: u16, word: bool) -> u16 {
+    let (mask, sign) = width(word);
+    let result = result as u32 & mask;
+    let flags = set(flags, ZF, result == 0);
+    let flags = set(flags, SF, result & sign != 0);
+    // parity only ever looks at the low byte
+    set(flags, PF, (result as u8).count_ones() & 1 == 0)
+}
+
+// a + b + carry, returns the result and the new flags
+pub fn add(flags: u16, a: u16, b: u16, carry: bool, word: bool) -> (u16, u16) {
+    let (mask, sign) = width(word);
+    let (a, b) = (a as u32 & mask, b as u32 & mask);
+    let sum = a + b + carry as u32;
+    let value = sum & mask;
+
+    let flags = result(flags, value as u16, word);
+    let flags = set(flags, CF, sum > mask);
+    let flags = set(flags, AF, (a ^ b ^ value) & 0x10 != 0);
+    let flags = set(flags, OF, !(a ^ b) & (a ^ value) & sign != 0);
+    (value as u16, flags)
+}
+
+// a - b - borrow, returns the result and the new flags
+pub fn sub(flags: u16, a: u16, b: u16, borrow: bool, word: bool) -> (u16, u16) {
+    let (mask, sign) = width(word);
+    let (a, b) = (a as u32 & mask, b as u32 & mask);
+    let value = a.wrapping_sub(b).wrapping_sub(borrow as u32) & mask;
+
+    let flags = result(flags, value as u16, word);
+    let flags = set(flags, CF, b + borrow as u32 > a);
+    let flags = set(flags, AF, (a ^ b ^ value) & 0x10 != 0);
+    let flags = set(flags, OF, (a ^ b) & (a ^ value) & sign != 0);
+    (value as u16, flags)
+}
+
+// and, or, xor and test clear CF and OF, the 8086 clears AF too
+pub fn logic(flags: u16, value: u16, word: bool) -> u16 {
+    let flags = result(flags, value, word);
+    flags & !(CF | OF | AF)
+}
+
+// the result and flags of an arithmetic or logic opcode, b is ignored by the one operand forms
+// cmp and test give the result the flags came from, it's up to the caller not to store it
+// none for an opcode that doesn't go through the alu
+pub fn alu(opcode: Opcode, flags: u16, a: u16, b: u16, word: bool) -> Option<(u16, u16)> {
+    let (mask, _) = width(word);
+    let carry = flags & CF != 0;
+    let (value, new_flags) = match opcode {
+        Opcode::ADD => add(flags, a, b, false, word),
+        Opcode::ADC => add(flags, a, b, carry, word),
+        Opcode::SUB | Opcode::CMP => sub(flags, a, b, false, word),
+        Opcode::SBB => sub(flags, a, b, carry, word),
+        Opcode::NEG => sub(flags, 0, a, false, word),
+        // inc and dec leave the carry alone
+        Opcode::INC => {
+            let (value, new_flags) = add(flags, a, 1, false, word);
+            (value, set(new_flags, CF, carry))
+        }
+        Opcode::DEC => {
+            let (value, new_flags) = sub(flags, a, 1, false, word);
+            (value, set(new_flags, CF, carry))
+        }
+        Opcode::AND | Opcode::TEST => {
+            let value = a & b;
+            (value, logic(flags, value, word))
+        }
+        Opcode::OR => {
+            let value = a | b;
+            (value, logic(flags, value, word))
+        }
+        Opcode::XOR => {
+            let value = a ^ b;
+            (value, logic(flags, value, word))
+        }
+        // not changes no flags
+        Opcode::NOT => (!a, flags),
+        _ => return None,
+    };
+    Some((value & mask as u16, new_flags))
+}
@@ -1,395 +1,430 @@
 use lowercase_display_derive::LowercaseDisplay;
-use std::collections::VecDeque;
 use std::fs;
-use std::io::Result;
+use std::io;
+
+use crate::opcode_table::{Mnemonic, OperandSpec, OPCODE_TABLE};
 
 pub struct Decoder {
-    pub instruction_queue: VecDeque<u8>,
+    // the whole input, decoding reads it without consuming it
+    pub image: Vec<u8>,
     pub intermediate_repr: Vec<Instruction>,
 }
 
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl From<Vec<u8>> for Decoder {
+    fn from(bytes: Vec<u8>) -> Self {
+        let mut decoder = Decoder::new();
+        decoder.image = bytes;
+        decoder
+    }
+}
+
 impl Decoder {
     pub fn new() -> Self {
         Decoder {
-            instruction_queue: VecDeque::new(),
+            image: Vec::new(),
             intermediate_repr: Vec::new(),
         }
     }
 
-    pub fn load(&mut self, file_name: &str) -> Result<()> {
-        let file_content = fs::read(file_name).expect("this should work");
+    pub fn load(&mut self, file_name: &str) -> Result<(), DecodeError> {
+        let file_content = fs::read(file_name)?;
 
-        self.instruction_queue.extend(file_content);
+        self.image.extend(file_content);
         Ok(())
     }
 
     pub fn dump_memory(&self) {
-        for i in self.instruction_queue.iter() {
+        for i in self.image.iter() {
             print!("{:08b} ", i);
         }
-        println!("")
-    }
-
-    pub fn decode(&mut self) {
-        while let Some(byte) = self.instruction_queue.pop_front() {
-            let mut w: Option<u8> = None;
-            let mut d: Option<u8> = None;
-            let mut s: Option<u8> = None;
-            // Get instruction Opcode
-            let (mut opcode, kind) = Self::match_opcode(&byte);
-
-            if let Some(InstructionKind::ImmediateToAccumulator) = kind {
-                w = Some((&byte << 7) >> 7);
-                let third_byte = self.instruction_queue.pop_front().unwrap();
-
-                if w == Some(1) {
-                    let fourth_byte = self.instruction_queue.pop_front().unwrap();
-                    self.intermediate_repr.push(Instruction {
-                        opcode: opcode.unwrap(),
-                        operands: [
-                            Operand::FieldEncoding(FieldEncoding::Reg(Register::AX), None),
-                            Operand::RawData(
-                                RawData::U16(u16::from_le_bytes([third_byte, fourth_byte])),
-                                None,
-                            ),
-                        ],
-                    })
-                } else {
-                    self.intermediate_repr.push(Instruction {
-                        opcode: opcode.unwrap(),
-                        operands: [
-                            Operand::FieldEncoding(FieldEncoding::Reg(Register::AL), None),
-                            Operand::RawData(RawData::I8(third_byte as i8), None),
-                        ],
+        println!()
+    }
+
+    pub fn dump_listing(&self) {
+        for instruction in self.intermediate_repr.iter() {
+            println!("{}", instruction.listing())
+        }
+    }
+
+    // instruction starting exactly at address, e.g. the one ip points at
+    pub fn instruction_at(&self, address: usize) -> Option<&Instruction> {
+        self.intermediate_repr
+            .binary_search_by_key(&address, |instruction| instruction.address)
+            .ok()
+            .map(|i| &self.intermediate_repr[i])
+    }
+
+    // decodes the whole image from the start, replacing anything decoded before
+    // on error the instructions decoded up to that point are kept in intermediate_repr
+    pub fn decode(&mut self) -> Result<(), DecodeError> {
+        self.intermediate_repr.clear();
+        for instruction in Instructions::new(&self.image, 0) {
+            self.intermediate_repr.push(instruction?);
+        }
+        Ok(())
+    }
+
+    // lazily decodes the image from the start
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions::new(&self.image, 0)
+    }
+
+    // decodes the one instruction starting at offset, prefixes included, and returns it with its length
+    pub fn decode_at(bytes: &[u8], offset: usize) -> Result<(Instruction, usize), DecodeError> {
+        let mut cursor = Cursor {
+            bytes,
+            position: offset,
+        };
+        let mut prefixes = Prefixes::default();
+        let (byte, entry) = loop {
+            let opcode_offset = cursor.position;
+            let byte = cursor.next_byte()?;
+            let entry = &OPCODE_TABLE[byte as usize];
+            match entry.mnemonic {
+                // prefixes are collected until the instruction they apply to is decoded
+                Mnemonic::Prefix => Decoder::match_prefix(&byte, &mut prefixes),
+                Mnemonic::Invalid => {
+                    return Err(DecodeError::UnknownOpcode {
+                        offset: opcode_offset,
+                        byte,
                     })
                 }
-            } else if let Some(InstructionKind::FourBitOpcode) = kind {
-                w = Some((&byte << 4) >> 7);
-                let reg_numeric_value = (&byte << 5) >> 5;
-                let reg_field = Decoder::get_reg_field(&reg_numeric_value, &w.unwrap());
-                let data: Option<RawData> = match w {
-                    Some(1) => {
-                        let low = self.instruction_queue.pop_front().unwrap() as u16;
-                        let high = self.instruction_queue.pop_front().unwrap() as u16;
-                        Some(RawData::U16((high << 8) | low))
-                    }
-                    Some(0) => Some(RawData::U8(self.instruction_queue.pop_front().unwrap())),
-                    _ => None,
-                };
-                self.append_intermediate_repr(
-                    d.as_ref(),
-                    opcode.unwrap(),
-                    Operand::RawData(data.unwrap(), None),
-                    Operand::FieldEncoding(reg_field, None),
-                );
-            } else if let Some(InstructionKind::SixBitOpcode) = kind {
-                d = Some((&byte << 6) >> 7);
-                w = Some((&byte << 7) >> 7);
-                // finished with the first byte, I now know opcode, d, w
-                // load second byte
-                // now get the mod
-                let second_byte: u8 = self.instruction_queue.pop_front().unwrap();
-                let mode: u8 = second_byte >> 6;
-                let reg = (second_byte << 2) >> 5;
-                let rm = (second_byte << 5) >> 5;
-                // WILDCARD handling immediate to register / memory - if memory, there could be a displacement
-                // if first byte is 100000SW, opcode is encoded by reg field
-                if let Some(Opcode::WILDCARD) = opcode {
-                    opcode = Decoder::match_wildcard_opcode(&reg);
-                    s = Some((&byte << 6) >> 7);
-                    d = None;
-                }
-                // 00 - memory mode, no displacement (unless rm = 110 - direct address)
-                // 01 - memory mode, 8 bit
-                // 10 - memory mode, 16 bit
-                // 11 - register mode
-                match mode {
-                    0 => {
-                        let mut field1: Option<Operand> = None;
-                        let mut explicit_size: Option<ExplicitSize> = None;
-                        let field2 = Decoder::get_rm_field(&rm, None);
-                        if let None = d {
-                            let third_byte = self.instruction_queue.pop_front().unwrap();
-                            if w == Some(1) && s == Some(0) {
-                                // if s:w = 01 ??
-                                explicit_size = Some(ExplicitSize::Word);
-                                let fourth_byte = self.instruction_queue.pop_front().unwrap();
-                                field1 = Some(Operand::RawData(
-                                    RawData::U16(u16::from_le_bytes([third_byte, fourth_byte])),
-                                    None,
-                                ))
-                            } else {
-                                explicit_size = Some(ExplicitSize::Byte);
-                                field1 = Some(Operand::RawData(RawData::U8(third_byte), None));
-                            }
-                        } else {
-                            field1 = Some(Operand::FieldEncoding(
-                                Decoder::get_reg_field(&reg, &w.unwrap()),
-                                None,
-                            ));
-                        }
-
-                        self.append_intermediate_repr(
-                            d.as_ref(),
-                            opcode.unwrap(),
-                            field1.unwrap(),
-                            Operand::FieldEncoding(field2, explicit_size),
-                        );
-                    }
-                    1 => {
-                        let third_byte = self.instruction_queue.pop_front().unwrap() as i16;
-
-                        let field1 = Decoder::get_reg_field(&reg, &w.unwrap());
-                        let field2 = Decoder::get_rm_field(&rm, Some(third_byte));
-
-                        self.append_intermediate_repr(
-                            d.as_ref(),
-                            opcode.unwrap(),
-                            Operand::FieldEncoding(field1, None),
-                            Operand::FieldEncoding(field2, None),
-                        );
-                    }
-                    2 => {
-                        let mut explicit_size: Option<ExplicitSize> = None;
-                        let third_byte = self.instruction_queue.pop_front().unwrap();
-                        let fourth_byte = self.instruction_queue.pop_front().unwrap();
-                        let displacement = i16::from_le_bytes([third_byte, fourth_byte]);
-
-                        let mut field1: Option<Operand> = None;
-                        let field2 = Decoder::get_rm_field(&rm, Some(displacement));
-
-                        if let None = d {
-                            let fifth_byte = self.instruction_queue.pop_front().unwrap();
-                            if w == Some(1) && s == Some(0) {
-                                explicit_size = Some(ExplicitSize::Word);
-                                let sixth_byte = self.instruction_queue.pop_front().unwrap();
-                                field1 = Some(Operand::RawData(
-                                    RawData::U16(u16::from_le_bytes([fifth_byte, sixth_byte])),
-                                    None,
-                                ))
-                            } else {
-                                explicit_size = Some(ExplicitSize::Byte);
-                                field1 = Some(Operand::RawData(RawData::U8(fifth_byte), None));
-                            }
-                        } else {
-                            field1 = Some(Operand::FieldEncoding(
-                                Decoder::get_reg_field(&reg, &w.unwrap()),
-                                None,
-                            ));
-                        }
-
-                        self.append_intermediate_repr(
-                            d.as_ref(),
-                            opcode.unwrap(),
-                            field1.unwrap(),
-                            Operand::FieldEncoding(field2, explicit_size),
-                        );
-                    }
-                    3 => {
-                        let mut field1: Option<Operand> = None;
-                        let field2 =
-                            Operand::FieldEncoding(Decoder::get_reg_field(&rm, &w.unwrap()), None);
-                        if let None = d {
-                            let third_byte = self.instruction_queue.pop_front().unwrap();
-                            if w == Some(1) && s == Some(0) {
-                                // if s:w = 01 ??
-                                let fourth_byte = self.instruction_queue.pop_front().unwrap();
-                                field1 = Some(Operand::RawData(
-                                    RawData::U16(u16::from_le_bytes([third_byte, fourth_byte])),
-                                    None,
-                                ))
-                            } else {
-                                field1 = Some(Operand::RawData(RawData::U8(third_byte), None));
-                            }
-                        } else {
-                            field1 = Some(Operand::FieldEncoding(
-                                Decoder::get_reg_field(&reg, &w.unwrap()),
-                                None,
-                            ));
-                        }
-                        self.append_intermediate_repr(
-                            d.as_ref(),
-                            opcode.unwrap(),
-                            field1.unwrap(),
-                            field2,
-                        );
-                    }
-                    _ => (),
-                }
-            } else {
-                println!("{:08b}", byte);
-                println!("Memory dump before panic");
-                self.execute();
-                panic!("Unrecognized instruction")
+                _ => break (byte, entry),
             }
+        };
+        let opcode_offset = cursor.position - 1;
+        let unknown = DecodeError::UnknownOpcode {
+            offset: opcode_offset,
+            byte,
+        };
+        // mod reg r/m
+        let mod_rm = match entry.has_mod_rm() {
+            true => {
+                let second_byte = cursor.next_byte()?;
+                Some((
+                    second_byte >> 6,
+                    (second_byte >> 3) & 0b111,
+                    second_byte & 0b111,
+                ))
+            }
+            false => None,
+        };
+        let (opcode, specs) = match (entry.mnemonic, mod_rm) {
+            (Mnemonic::Op(opcode), _) => (opcode, entry.operands),
+            (Mnemonic::Group(group), Some((_, reg, _))) => match group[reg as usize] {
+                Some(member) => member,
+                None => return Err(unknown),
+            },
+            _ => return Err(unknown),
+        };
+        // a far pointer or an address only exists in memory, mod = 11 has no encoding
+        if let Some((3, _, _)) = mod_rm {
+            if specs.contains(&OperandSpec::FarRm) || specs.contains(&OperandSpec::Mem) {
+                return Err(unknown);
+            }
+        }
+        // any displacement comes before the immediate data
+        let mut rm_field = match mod_rm {
+            Some((mode, _, rm)) => Some(cursor.get_rm_operand(&mode, &rm, &entry.w)?),
+            None => None,
+        };
+        let mut operands = Vec::with_capacity(specs.len());
+        for spec in specs {
+            operands.push(cursor.decode_operand(
+                spec,
+                opcode_offset,
+                byte,
+                entry.w,
+                mod_rm,
+                &mut rm_field,
+            )?);
         }
+        let length = cursor.position - offset;
+        let mut instruction = Instruction::new(opcode, operands);
+        instruction.prefixes = prefixes;
+        instruction.bytes = bytes[offset..cursor.position].to_vec();
+        instruction.address = offset;
+        Ok((instruction, length))
     }
 
-    pub fn execute(&self) {
-        for val in self.intermediate_repr.iter() {
-            println!("{}", val)
+    // records a byte the opcode table marks as a prefix
+    fn match_prefix(byte: &u8, prefixes: &mut Prefixes) {
+        match byte {
+            0x26 | 0x2E | 0x36 | 0x3E => {
+                if let FieldEncoding::Reg(segment) = Decoder::get_segment_field(&(byte >> 3)) {
+                    prefixes.segment = Some(segment);
+                }
+            }
+            0xF0 => prefixes.lock = true,
+            0xF2 => prefixes.repeat = Some(Repeat::Repne),
+            0xF3 => prefixes.repeat = Some(Repeat::Rep),
+            _ => (),
         }
     }
 
-    fn append_intermediate_repr(
-        &mut self,
-        d: Option<&u8>,
-        opcode: Opcode,
-        field_reg: Operand,
-        field_rm: Operand,
-    ) {
-        match d {
-            Some(1) => self.intermediate_repr.push(Instruction {
-                opcode,
-                operands: [field_reg, field_rm],
-            }),
-            // if d is not specified, the rm field is the destination (immediate to reg/memory) so reg field will be RawData
-            _ => self.intermediate_repr.push(Instruction {
-                opcode,
-                operands: [field_rm, field_reg],
-            }),
-        }
+    fn get_rm_field(rm: &u8, disp: Option<i16>) -> FieldEncoding {
+        // 110 with no displacement is a direct address, handled by the caller
+        let (base, index) = match rm & 0b111 {
+            0 => (Register::BX, Some(Register::SI)),
+            1 => (Register::BX, Some(Register::DI)),
+            2 => (Register::BP, Some(Register::SI)),
+            3 => (Register::BP, Some(Register::DI)),
+            4 => (Register::SI, None),
+            5 => (Register::DI, None),
+            6 => (Register::BP, None),
+            _ => (Register::BX, None),
+        };
+        FieldEncoding::Indexed(base, index, disp)
     }
 
-    fn match_wildcard_opcode(reg: &u8) -> Option<Opcode> {
-        match reg {
-            0 => Some(Opcode::ADD),
-            2 => Some(Opcode::ADC),
-            5 => Some(Opcode::SUB),
-            _ => None,
+    fn get_segment_field(sr: &u8) -> FieldEncoding {
+        match sr & 0b11 {
+            0 => FieldEncoding::Reg(Register::ES),
+            1 => FieldEncoding::Reg(Register::CS),
+            2 => FieldEncoding::Reg(Register::SS),
+            _ => FieldEncoding::Reg(Register::DS),
         }
     }
 
-    fn match_opcode(byte: &u8) -> (Option<Opcode>, Option<InstructionKind>) {
-        match byte >> 1 {
-            2 => (
-                Some(Opcode::ADD),
-                Some(InstructionKind::ImmediateToAccumulator),
-            ),
-            10 => (
-                Some(Opcode::ADC),
-                Some(InstructionKind::ImmediateToAccumulator),
-            ),
-            22 => (
-                Some(Opcode::SUB),
-                Some(InstructionKind::ImmediateToAccumulator),
-            ),
-            _ => match byte >> 2 {
-                0 => (Some(Opcode::ADD), Some(InstructionKind::SixBitOpcode)),
-                32 => (Some(Opcode::WILDCARD), Some(InstructionKind::SixBitOpcode)),
-                34 => (Some(Opcode::MOV), Some(InstructionKind::SixBitOpcode)),
-                _ => match byte >> 4 {
-                    11 => (Some(Opcode::MOV), Some(InstructionKind::FourBitOpcode)),
-                    _ => (None, None),
-                },
+    fn get_reg_field(reg: &u8, w: &u8) -> FieldEncoding {
+        let register = match w {
+            0 => match reg & 0b111 {
+                0 => Register::AL,
+                1 => Register::CL,
+                2 => Register::DL,
+                3 => Register::BL,
+                4 => Register::AH,
+                5 => Register::CH,
+                6 => Register::DH,
+                _ => Register::BH,
+            },
+            _ => match reg & 0b111 {
+                0 => Register::AX,
+                1 => Register::CX,
+                2 => Register::DX,
+                3 => Register::BX,
+                4 => Register::SP,
+                5 => Register::BP,
+                6 => Register::SI,
+                _ => Register::DI,
             },
+        };
+        FieldEncoding::Reg(register)
+    }
+}
+
+// decodes one instruction per call to next, stops after the end of the input or the first error
+pub struct Instructions<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    failed: bool,
+}
+
+impl<'a> Instructions<'a> {
+    pub fn new(bytes: &'a [u8], offset: usize) -> Self {
+        Instructions {
+            bytes,
+            offset,
+            failed: false,
         }
     }
 
-    fn get_rm_field(rm: &u8, disp: Option<i16>) -> FieldEncoding {
-        let mut rm_field: Option<FieldEncoding> = None;
-
-        rm_field = match disp {
-            None => match rm {
-                0 => Some(FieldEncoding::Indexed(
-                    Register::BX,
-                    Some(Register::SI),
-                    None,
-                )),
-                1 => Some(FieldEncoding::Indexed(
-                    Register::BX,
-                    Some(Register::DI),
-                    None,
-                )),
-                2 => Some(FieldEncoding::Indexed(
-                    Register::BP,
-                    Some(Register::SI),
-                    None,
-                )),
-                3 => Some(FieldEncoding::Indexed(
-                    Register::BP,
-                    Some(Register::DI),
-                    None,
-                )),
-                4 => Some(FieldEncoding::Reg(Register::SI)),
-                5 => Some(FieldEncoding::Reg(Register::DI)),
-                6 => Some(FieldEncoding::Reg(Register::BP)),
-                7 => Some(FieldEncoding::Reg(Register::BX)),
-                _ => panic!("R/M out of range"),
+    // where the next instruction starts, or where decoding failed
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl Iterator for Instructions<'_> {
+    type Item = Result<Instruction, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.offset >= self.bytes.len() {
+            return None;
+        }
+        match Decoder::decode_at(self.bytes, self.offset) {
+            Ok((instruction, length)) => {
+                self.offset += length;
+                Some(Ok(instruction))
+            }
+            Err(err) => {
+                self.failed = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+impl std::iter::FusedIterator for Instructions<'_> {}
+
+// reads one instruction out of a byte slice
+struct Cursor<'a> {
+    bytes: &'a [u8],
+    // offset of the next byte to read
+    position: usize,
+}
+
+impl Cursor<'_> {
+    // reads one operand as the opcode table describes it, rm_field is the already decoded r/m operand
+    fn decode_operand(
+        &mut self,
+        spec: &OperandSpec,
+        opcode_offset: usize,
+        byte: u8,
+        w: u8,
+        mod_rm: Option<(u8, u8, u8)>,
+        rm_field: &mut Option<FieldEncoding>,
+    ) -> Result<Operand, DecodeError> {
+        let (mode, reg, _) = mod_rm.unwrap_or_default();
+        let register = |register| Operand::FieldEncoding(FieldEncoding::Reg(register), None);
+        Ok(match spec {
+            OperandSpec::Rm | OperandSpec::SizedRm | OperandSpec::FarRm | OperandSpec::Mem => {
+                // a table row with an r/m operand but no mod r/m byte, or with two r/m operands
+                let field = rm_field.take().ok_or(DecodeError::UnknownOpcode {
+                    offset: opcode_offset,
+                    byte,
+                })?;
+                // only a memory operand without a register next to it needs its size spelled out
+                let explicit_size = match (spec, mode) {
+                    (OperandSpec::Rm | OperandSpec::Mem, _) | (_, 3) => None,
+                    (OperandSpec::FarRm, _) => Some(ExplicitSize::Far),
+                    _ => Some(ExplicitSize::from_w(&w)),
+                };
+                Operand::FieldEncoding(field, explicit_size)
+            }
+            OperandSpec::Reg => Operand::FieldEncoding(Decoder::get_reg_field(&reg, &w), None),
+            OperandSpec::Segment => Operand::FieldEncoding(Decoder::get_segment_field(&reg), None),
+            OperandSpec::OpcodeReg => {
+                Operand::FieldEncoding(Decoder::get_reg_field(&byte, &w), None)
+            }
+            // 000sr11x
+            OperandSpec::OpcodeSegment => {
+                Operand::FieldEncoding(Decoder::get_segment_field(&(byte >> 3)), None)
+            }
+            OperandSpec::Accumulator => match w {
+                1 => register(Register::AX),
+                _ => register(Register::AL),
             },
-            _ => match rm {
-                0 => Some(FieldEncoding::Indexed(
-                    Register::BX,
-                    Some(Register::SI),
-                    disp,
-                )),
-                1 => Some(FieldEncoding::Indexed(
-                    Register::BX,
-                    Some(Register::DI),
-                    disp,
-                )),
-                2 => Some(FieldEncoding::Indexed(
-                    Register::BP,
-                    Some(Register::SI),
-                    disp,
-                )),
-                3 => Some(FieldEncoding::Indexed(
-                    Register::BP,
-                    Some(Register::DI),
-                    disp,
-                )),
-                4 => Some(FieldEncoding::Indexed(Register::SI, None, disp)),
-                5 => Some(FieldEncoding::Indexed(Register::DI, None, disp)),
-                6 => Some(FieldEncoding::Indexed(Register::BP, None, disp)),
-                7 => Some(FieldEncoding::Indexed(Register::BX, None, disp)),
-                _ => panic!("R/M out of range"),
+            OperandSpec::Immediate => Operand::RawData(self.get_immediate_data(&w, &0)?, None),
+            OperandSpec::SignedImmediate => match w {
+                1 => Operand::RawData(self.get_immediate_data(&w, &0)?, None),
+                _ => Operand::RawData(RawData::I8(self.next_byte()? as i8), None),
             },
-        };
-        rm_field.unwrap()
+            OperandSpec::SignExtended => Operand::RawData(self.get_immediate_data(&1, &1)?, None),
+            OperandSpec::ImmediateByte => Operand::RawData(RawData::U8(self.next_byte()?), None),
+            OperandSpec::ImmediateWord => Operand::RawData(self.get_immediate_data(&1, &0)?, None),
+            OperandSpec::Direct => {
+                let low = self.next_byte()?;
+                let high = self.next_byte()?;
+                Operand::FieldEncoding(FieldEncoding::Direct(u16::from_le_bytes([low, high])), None)
+            }
+            OperandSpec::Short => Operand::Relative(self.next_byte()? as i8 as i16),
+            OperandSpec::Near => {
+                let low = self.next_byte()?;
+                let high = self.next_byte()?;
+                Operand::Relative(i16::from_le_bytes([low, high]))
+            }
+            // the offset comes first, then the segment
+            OperandSpec::Far => {
+                let offset = u16::from_le_bytes([self.next_byte()?, self.next_byte()?]);
+                let segment = u16::from_le_bytes([self.next_byte()?, self.next_byte()?]);
+                Operand::FarPointer(segment, offset)
+            }
+            // 11011xxx mod yyy r/m - xxxyyy is the opcode for the coprocessor
+            OperandSpec::Escape => Operand::RawData(RawData::U8(((byte & 0b111) << 3) | reg), None),
+            OperandSpec::One => Operand::RawData(RawData::U8(1), None),
+            OperandSpec::CL => register(Register::CL),
+            OperandSpec::DX => register(Register::DX),
+            OperandSpec::AX => register(Register::AX),
+        })
     }
 
-    fn get_reg_field(reg: &u8, w: &u8) -> FieldEncoding {
-        let mut reg_field: Option<FieldEncoding> = None;
-        match w {
-            0 => {
-                reg_field = match reg {
-                    0 => Some(FieldEncoding::Reg(Register::AL)),
-                    1 => Some(FieldEncoding::Reg(Register::CL)),
-                    2 => Some(FieldEncoding::Reg(Register::DL)),
-                    3 => Some(FieldEncoding::Reg(Register::BL)),
-                    4 => Some(FieldEncoding::Reg(Register::AH)),
-                    5 => Some(FieldEncoding::Reg(Register::CH)),
-                    6 => Some(FieldEncoding::Reg(Register::DH)),
-                    7 => Some(FieldEncoding::Reg(Register::BH)),
-                    _ => None,
-                }
+    // reads the displacement or direct address the mod and r/m fields call for
+    fn get_rm_operand(&mut self, mode: &u8, rm: &u8, w: &u8) -> Result<FieldEncoding, DecodeError> {
+        // 00 - memory mode, no displacement (unless rm = 110 - direct address)
+        // 01 - memory mode, 8 bit
+        // 10 - memory mode, 16 bit
+        // 11 - register mode
+        Ok(match mode {
+            0 if *rm == 6 => {
+                let low = self.next_byte()?;
+                let high = self.next_byte()?;
+                FieldEncoding::Direct(u16::from_le_bytes([low, high]))
             }
-            1 => {
-                reg_field = match reg {
-                    0 => Some(FieldEncoding::Reg(Register::AX)),
-                    1 => Some(FieldEncoding::Reg(Register::CX)),
-                    2 => Some(FieldEncoding::Reg(Register::DX)),
-                    3 => Some(FieldEncoding::Reg(Register::BX)),
-                    4 => Some(FieldEncoding::Reg(Register::SP)),
-                    5 => Some(FieldEncoding::Reg(Register::BP)),
-                    6 => Some(FieldEncoding::Reg(Register::SI)),
-                    7 => Some(FieldEncoding::Reg(Register::DI)),
-                    _ => None,
-                }
+            0 => Decoder::get_rm_field(rm, None),
+            1 => Decoder::get_rm_field(rm, Some(self.next_byte()? as i8 as i16)),
+            2 => {
+                let low = self.next_byte()?;
+                let high = self.next_byte()?;
+                Decoder::get_rm_field(rm, Some(i16::from_le_bytes([low, high])))
             }
-            _ => (),
+            _ => Decoder::get_reg_field(rm, w),
+        })
+    }
+
+    fn next_byte(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .ok_or(DecodeError::TruncatedInstruction {
+                offset: self.position,
+            })?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    // s:w = 01 carries a full 16 bit immediate, s:w = 11 a single byte sign extended to 16 bits
+    fn get_immediate_data(&mut self, w: &u8, s: &u8) -> Result<RawData, DecodeError> {
+        let low = self.next_byte()?;
+        Ok(match (s, w) {
+            (0, 1) => {
+                let high = self.next_byte()?;
+                RawData::U16(u16::from_le_bytes([low, high]))
+            }
+            (1, 1) => RawData::I16(low as i8 as i16),
+            _ => RawData::U8(low),
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    // offset and value of the byte that doesn't start any known instruction
+    UnknownOpcode { offset: usize, byte: u8 },
+    // the input ended at offset in the middle of an instruction
+    TruncatedInstruction { offset: usize },
+    Io(io::Error),
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::UnknownOpcode { offset, byte } => {
+                write!(f, "unknown opcode {:08b} at offset {}", byte, offset)
+            }
+            DecodeError::TruncatedInstruction { offset } => {
+                write!(f, "instruction truncated at offset {}", offset)
+            }
+            DecodeError::Io(err) => write!(f, "{}", err),
         }
-        reg_field.unwrap()
     }
 }
 
-enum InstructionKind {
-    FourBitOpcode,
-    SixBitOpcode,
-    ImmediateToAccumulator,
+impl std::error::Error for DecodeError {}
+
+impl From<io::Error> for DecodeError {
+    fn from(err: io::Error) -> Self {
+        DecodeError::Io(err)
+    }
 }
 
-#[derive(LowercaseDisplay, Debug, PartialEq)]
+#[derive(LowercaseDisplay, Debug, Clone, PartialEq)]
 pub enum Register {
     AX,
     AL,
@@ -407,43 +442,151 @@ pub enum Register {
     SI,
     SP,
     BP,
+    ES,
+    CS,
+    SS,
+    DS,
 }
 
-#[derive(Debug)]
+impl Register {
+    pub fn is_word(&self) -> bool {
+        !matches!(
+            self,
+            Register::AL
+                | Register::AH
+                | Register::BL
+                | Register::BH
+                | Register::CL
+                | Register::CH
+                | Register::DL
+                | Register::DH
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Instruction {
     pub opcode: Opcode,
-    pub operands: [Operand; 2],
-    // pub field_one: Operand,
-    // pub field_two: Operand,
+    // zero to two operands, destination first
+    pub operands: Vec<Operand>,
+    pub prefixes: Prefixes,
+    // offset of the first byte (prefixes included) in the decoded input
+    pub address: usize,
+    // the encoded instruction, its length is the instruction size
+    pub bytes: Vec<u8>,
+}
+
+impl Instruction {
+    pub fn new(opcode: Opcode, operands: Vec<Operand>) -> Self {
+        Instruction {
+            opcode,
+            operands,
+            prefixes: Prefixes::default(),
+            address: 0,
+            bytes: Vec::new(),
+        }
+    }
+
+    // absolute address a relative jump lands on, counted from the end of the instruction
+    pub fn jump_target(&self) -> Option<usize> {
+        self.operands.iter().find_map(|operand| match operand {
+            // ip wraps around within the code segment
+            Operand::Relative(disp) => {
+                Some(((self.address + self.bytes.len()) as u16).wrapping_add(*disp as u16) as usize)
+            }
+            _ => None,
+        })
+    }
+
+    // `0000: 89 D9  mov cx, bx`
+    // the byte column fits six bytes of instruction behind a segment, lock and rep prefix each,
+    // only repeated prefixes push the mnemonic further out
+    pub fn listing(&self) -> String {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        format!("{:04x}: {:<26} {}", self.address, bytes.join(" "), self)
+    }
+
+    fn fmt_operand(&self, operand: &Operand, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (operand, &self.prefixes.segment) {
+            (
+                Operand::FieldEncoding(
+                    field @ (FieldEncoding::Indexed(..) | FieldEncoding::Direct(_)),
+                    size,
+                ),
+                Some(segment),
+            ) => match size {
+                Some(size) => write!(f, "{} {}:{}", size, segment, field),
+                None => write!(f, "{}:{}", segment, field),
+            },
+            (Operand::Relative(_), _) => match self.jump_target() {
+                Some(target) => write!(f, "0x{:04x}", target),
+                None => write!(f, "{}", operand),
+            },
+            _ => write!(f, "{}", operand),
+        }
+    }
 }
 
 impl std::fmt::Display for Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{} {}, {}",
-            self.opcode, self.operands[0], self.operands[1]
-        )
+        if self.prefixes.lock {
+            write!(f, "lock ")?;
+        }
+        if let Some(repeat) = &self.prefixes.repeat {
+            write!(f, "{} ", repeat)?;
+        }
+        // an override with no memory operand to print it on goes before the mnemonic, cs movsb
+        let memory_operand = self.operands.iter().any(|operand| {
+            matches!(
+                operand,
+                Operand::FieldEncoding(FieldEncoding::Indexed(..) | FieldEncoding::Direct(_), _)
+            )
+        });
+        if let (Some(segment), false) = (&self.prefixes.segment, memory_operand) {
+            write!(f, "{} ", segment)?;
+        }
+        write!(f, "{}", self.opcode)?;
+        for (i, operand) in self.operands.iter().enumerate() {
+            match i {
+                0 => write!(f, " ")?,
+                _ => write!(f, ", ")?,
+            }
+            self.fmt_operand(operand, f)?;
+        }
+        Ok(())
     }
 }
 
+// where an instruction was decoded from doesn't make it a different instruction
 impl PartialEq for Instruction {
     fn eq(&self, other: &Self) -> bool {
-        if self.opcode != other.opcode {
-            return false;
-        } else if self.operands[0] != other.operands[0] {
-            return false;
-        } else if self.operands[1] != other.operands[1] {
-            return false;
-        }
-        true
+        self.opcode == other.opcode
+            && self.operands == other.operands
+            && self.prefixes == other.prefixes
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Prefixes {
+    pub segment: Option<Register>,
+    pub lock: bool,
+    pub repeat: Option<Repeat>,
+}
+
+#[derive(LowercaseDisplay, Debug, Clone, PartialEq)]
+pub enum Repeat {
+    Rep,
+    Repne,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Operand {
     FieldEncoding(FieldEncoding, Option<ExplicitSize>),
     RawData(RawData, Option<ExplicitSize>),
+    // signed displacement of a jump, relative to the end of the instruction
+    Relative(i16),
+    // segment:offset of a far call or jump
+    FarPointer(u16, u16),
 }
 
 impl std::fmt::Display for Operand {
@@ -457,14 +600,27 @@ impl std::fmt::Display for Operand {
                 Some(size) => write!(f, "{} {}", size, val),
                 None => write!(f, "{}", val),
             },
+            // an Instruction prints the resolved target instead
+            Operand::Relative(disp) => write!(f, "{:+}", disp),
+            Operand::FarPointer(segment, offset) => write!(f, "0x{:04x}:0x{:04x}", segment, offset),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ExplicitSize {
     Word,
     Byte,
+    Far,
+}
+
+impl ExplicitSize {
+    pub fn from_w(w: &u8) -> Self {
+        match w {
+            1 => ExplicitSize::Word,
+            _ => ExplicitSize::Byte,
+        }
+    }
 }
 
 impl std::fmt::Display for ExplicitSize {
@@ -472,14 +628,16 @@ impl std::fmt::Display for ExplicitSize {
         match self {
             ExplicitSize::Byte => write!(f, "byte"),
             ExplicitSize::Word => write!(f, "word"),
+            ExplicitSize::Far => write!(f, "far"),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum FieldEncoding {
     Reg(Register),
     Indexed(Register, Option<Register>, Option<i16>),
+    Direct(u16),
 }
 
 impl std::fmt::Display for FieldEncoding {
@@ -503,15 +661,17 @@ impl std::fmt::Display for FieldEncoding {
                     None => write!(f, "[{}]", reg1),
                 },
             },
+            FieldEncoding::Direct(address) => write!(f, "[{}]", address),
         }
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum RawData {
     U8(u8),
     U16(u16),
     I8(i8),
+    I16(i16),
 }
 
 impl std::fmt::Display for RawData {
@@ -520,16 +680,107 @@ impl std::fmt::Display for RawData {
             RawData::U16(x) => write!(f, "{}", x),
             RawData::U8(x) => write!(f, "{}", x),
             RawData::I8(x) => write!(f, "{}", x),
+            RawData::I16(x) => write!(f, "{}", x),
         }
     }
 }
 
-#[derive(LowercaseDisplay, PartialEq, Debug)]
+#[derive(LowercaseDisplay, PartialEq, Debug, Clone, Copy)]
 pub enum Opcode {
     ADD,
+    OR,
     ADC,
-    CMP,
+    SBB,
+    AND,
     SUB,
+    XOR,
+    CMP,
+    TEST,
+    NOT,
+    NEG,
+    MUL,
+    IMUL,
+    DIV,
+    IDIV,
+    ROL,
+    ROR,
+    RCL,
+    RCR,
+    SHL,
+    SHR,
+    SAR,
+    INC,
+    DEC,
+    CALL,
+    JMP,
+    RET,
+    RETF,
+    INT,
+    // nasm only emits the one byte 0xcc for int3, `int 3` is 0xcd 0x03
+    INT3,
+    INTO,
+    IRET,
+    HLT,
+    WAIT,
+    ESC,
+    CMC,
+    CLC,
+    STC,
+    CLI,
+    STI,
+    CLD,
+    STD,
+    NOP,
+    AAA,
+    AAS,
+    AAM,
+    AAD,
+    DAA,
+    DAS,
+    CBW,
+    CWD,
     MOV,
-    WILDCARD,
+    PUSH,
+    POP,
+    XCHG,
+    XLAT,
+    LEA,
+    LDS,
+    LES,
+    LAHF,
+    SAHF,
+    PUSHF,
+    POPF,
+    IN,
+    OUT,
+    JO,
+    JNO,
+    JB,
+    JNB,
+    JE,
+    JNE,
+    JBE,
+    JA,
+    JS,
+    JNS,
+    JP,
+    JNP,
+    JL,
+    JNL,
+    JLE,
+    JG,
+    LOOP,
+    LOOPZ,
+    LOOPNZ,
+    JCXZ,
+    MOVSB,
+    MOVSW,
+    CMPSB,
+    CMPSW,
+    SCASB,
+    SCASW,
+    LODSB,
+    LODSW,
+    STOSB,
+    STOSW,
 }
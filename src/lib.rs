@@ -1,10 +1,12 @@
-#[allow(unused_assignments)]
+pub mod cpu;
+pub mod flags;
 pub mod instruction_decode;
+pub mod memory;
+mod opcode_table;
 
 #[cfg(test)]
 mod tests {
-    use std::collections::VecDeque;
-
+    use crate::cpu::*;
     use crate::instruction_decode::*;
 
     #[test]
@@ -12,47 +14,44 @@ mod tests {
         // add byte [bx], 34
         // 10000000 00000111 00100010
         let fake_instruction_stream: Vec<u8> = vec![128, 7, 34];
-        let expected: Instruction = Instruction {
-            opcode: Opcode::ADD,
-            operands: [
-                Operand::FieldEncoding(FieldEncoding::Reg(Register::BX), Some(ExplicitSize::Byte)),
+        let expected: Instruction = Instruction::new(
+            Opcode::ADD,
+            vec![
+                Operand::FieldEncoding(
+                    FieldEncoding::Indexed(Register::BX, None, None),
+                    Some(ExplicitSize::Byte),
+                ),
                 Operand::RawData(RawData::U8(34), None),
             ],
-        };
+        );
 
-        let mut p = Decoder {
-            intermediate_repr: VecDeque::new().into(),
-            instruction_queue: fake_instruction_stream.into(),
-        };
+        let mut p = Decoder::from(fake_instruction_stream);
 
-        p.decode();
+        p.decode().unwrap();
 
-        assert_eq!(expected, *p.intermediate_repr.get(0).unwrap());
+        assert_eq!(expected, *p.intermediate_repr.first().unwrap());
     }
 
     #[test]
     fn immediate_to_memory_with_displacement() {
-        // add [bp + si + 1000], 29
+        // add word [bp + si + 1000], 29
         // 10000011 10000010 11101000 00000011 00011101
         let fake_instruction_stream: Vec<u8> = vec![131, 130, 232, 3, 29];
-        let expected = Instruction {
-            opcode: Opcode::ADD,
-            operands: [
+        let expected = Instruction::new(
+            Opcode::ADD,
+            vec![
                 Operand::FieldEncoding(
                     FieldEncoding::Indexed(Register::BP, Some(Register::SI), Some(1000)),
-                    Some(ExplicitSize::Byte),
+                    Some(ExplicitSize::Word),
                 ),
-                Operand::RawData(RawData::U8(29), None),
+                Operand::RawData(RawData::I16(29), None),
             ],
-        };
-        let mut p = Decoder {
-            intermediate_repr: VecDeque::new().into(),
-            instruction_queue: fake_instruction_stream.into(),
-        };
+        );
+        let mut p = Decoder::from(fake_instruction_stream);
 
-        p.decode();
+        p.decode().unwrap();
 
-        assert_eq!(expected, *p.intermediate_repr.get(0).unwrap());
+        assert_eq!(expected, *p.intermediate_repr.first().unwrap());
     }
 
     #[test]
@@ -61,28 +60,1312 @@ mod tests {
         // add ax, 1000               add al, -30
         // 00000101 11101000 00000011 00000100 11100010
         let fake_instruction_stream: Vec<u8> = vec![5, 232, 3, 4, 226];
-        let expected_u16: Instruction = Instruction {
-            opcode: Opcode::ADD,
-            operands: [
+        let expected_u16: Instruction = Instruction::new(
+            Opcode::ADD,
+            vec![
                 Operand::FieldEncoding(FieldEncoding::Reg(Register::AX), None),
                 Operand::RawData(RawData::U16(1000), None),
             ],
-        };
-        let expected_i8 = Instruction {
-            opcode: Opcode::ADD,
-            operands: [
+        );
+        let expected_i8 = Instruction::new(
+            Opcode::ADD,
+            vec![
                 Operand::FieldEncoding(FieldEncoding::Reg(Register::AL), None),
                 Operand::RawData(RawData::I8(-30), None),
             ],
+        );
+        let mut p = Decoder::from(fake_instruction_stream);
+
+        p.decode().unwrap();
+
+        assert_eq!(expected_u16, *p.intermediate_repr.first().unwrap());
+        assert_eq!(expected_i8, *p.intermediate_repr.get(1).unwrap());
+    }
+
+    #[test]
+    fn conditional_jumps_and_loops() {
+        // test_label0:
+        // jnz test_label1            jnz test_label0
+        // test_label1:
+        // jnz test_label0            loopnz test_label1         jcxz test_label0
+        // 01110101 00000010 01110101 11111100 01110101 11111010 11100000 11111100 11100011 11110110
+        let fake_instruction_stream: Vec<u8> = vec![117, 2, 117, 252, 117, 250, 224, 252, 227, 246];
+        let expected = vec![
+            Instruction::new(Opcode::JNE, vec![Operand::Relative(2)]),
+            Instruction::new(Opcode::JNE, vec![Operand::Relative(-4)]),
+            Instruction::new(Opcode::JNE, vec![Operand::Relative(-6)]),
+            Instruction::new(Opcode::LOOPNZ, vec![Operand::Relative(-4)]),
+            Instruction::new(Opcode::JCXZ, vec![Operand::Relative(-10)]),
+        ];
+        let mut p = Decoder::from(fake_instruction_stream);
+
+        p.decode().unwrap();
+
+        assert_eq!(expected, p.intermediate_repr);
+        assert_eq!("jne 0x0004", p.intermediate_repr[0].to_string());
+        assert_eq!("jne 0x0000", p.intermediate_repr[1].to_string());
+        assert_eq!("jcxz 0x0000", p.intermediate_repr[4].to_string());
+    }
+
+    #[test]
+    fn arithmetic_and_logic_forms() {
+        // sub bx, [bx + si]          xor al, ah                 cmp ax, 1000
+        // 00101011 00011000          00110000 11100000          00111101 11101000 00000011
+        // and word [bp + si + 4], 29
+        // 10000001 01100010 00000100 00011101 00000000
+        // or bl, [bp - 2]
+        // 00001010 01011110 11111110
+        let fake_instruction_stream: Vec<u8> =
+            vec![43, 24, 48, 224, 61, 232, 3, 129, 98, 4, 29, 0, 10, 94, 254];
+        let expected = vec![
+            Instruction::new(
+                Opcode::SUB,
+                vec![
+                    Operand::FieldEncoding(FieldEncoding::Reg(Register::BX), None),
+                    Operand::FieldEncoding(
+                        FieldEncoding::Indexed(Register::BX, Some(Register::SI), None),
+                        None,
+                    ),
+                ],
+            ),
+            Instruction::new(
+                Opcode::XOR,
+                vec![
+                    Operand::FieldEncoding(FieldEncoding::Reg(Register::AL), None),
+                    Operand::FieldEncoding(FieldEncoding::Reg(Register::AH), None),
+                ],
+            ),
+            Instruction::new(
+                Opcode::CMP,
+                vec![
+                    Operand::FieldEncoding(FieldEncoding::Reg(Register::AX), None),
+                    Operand::RawData(RawData::U16(1000), None),
+                ],
+            ),
+            Instruction::new(
+                Opcode::AND,
+                vec![
+                    Operand::FieldEncoding(
+                        FieldEncoding::Indexed(Register::BP, Some(Register::SI), Some(4)),
+                        Some(ExplicitSize::Word),
+                    ),
+                    Operand::RawData(RawData::U16(29), None),
+                ],
+            ),
+            Instruction::new(
+                Opcode::OR,
+                vec![
+                    Operand::FieldEncoding(FieldEncoding::Reg(Register::BL), None),
+                    Operand::FieldEncoding(
+                        FieldEncoding::Indexed(Register::BP, None, Some(-2)),
+                        None,
+                    ),
+                ],
+            ),
+        ];
+        let mut p = Decoder::from(fake_instruction_stream);
+
+        p.decode().unwrap();
+
+        assert_eq!(expected, p.intermediate_repr);
+    }
+
+    #[test]
+    fn direct_address() {
+        // mov cx, [1234]             cmp word [4834], 29
+        // 10001011 00001110 11010010 00000100 10000001 00111110 11100010 00010010 00011101 00000000
+        // mov ax, [1234]             mov [16], al
+        // 10100001 11010010 00000100 10100010 00010000 00000000
+        let fake_instruction_stream: Vec<u8> = vec![
+            139, 14, 210, 4, 129, 62, 226, 18, 29, 0, 161, 210, 4, 162, 16, 0,
+        ];
+        let expected = vec![
+            Instruction::new(
+                Opcode::MOV,
+                vec![
+                    Operand::FieldEncoding(FieldEncoding::Reg(Register::CX), None),
+                    Operand::FieldEncoding(FieldEncoding::Direct(1234), None),
+                ],
+            ),
+            Instruction::new(
+                Opcode::CMP,
+                vec![
+                    Operand::FieldEncoding(FieldEncoding::Direct(4834), Some(ExplicitSize::Word)),
+                    Operand::RawData(RawData::U16(29), None),
+                ],
+            ),
+            Instruction::new(
+                Opcode::MOV,
+                vec![
+                    Operand::FieldEncoding(FieldEncoding::Reg(Register::AX), None),
+                    Operand::FieldEncoding(FieldEncoding::Direct(1234), None),
+                ],
+            ),
+            Instruction::new(
+                Opcode::MOV,
+                vec![
+                    Operand::FieldEncoding(FieldEncoding::Direct(16), None),
+                    Operand::FieldEncoding(FieldEncoding::Reg(Register::AL), None),
+                ],
+            ),
+        ];
+        let mut p = Decoder::from(fake_instruction_stream);
+
+        p.decode().unwrap();
+
+        assert_eq!(expected, p.intermediate_repr);
+        assert_eq!("cmp word [4834], 29", p.intermediate_repr[1].to_string());
+    }
+
+    #[test]
+    fn sign_extended_immediate() {
+        // add word [bp + si + 1000], -3                       sub cx, -12
+        // 10000011 10000010 11101000 00000011 11111101        10000011 11101001 11110100
+        // or byte [bx], 253
+        // 10000010 00001111 11111101
+        let fake_instruction_stream: Vec<u8> =
+            vec![131, 130, 232, 3, 253, 131, 233, 244, 130, 15, 253];
+        let expected = vec![
+            Instruction::new(
+                Opcode::ADD,
+                vec![
+                    Operand::FieldEncoding(
+                        FieldEncoding::Indexed(Register::BP, Some(Register::SI), Some(1000)),
+                        Some(ExplicitSize::Word),
+                    ),
+                    Operand::RawData(RawData::I16(-3), None),
+                ],
+            ),
+            Instruction::new(
+                Opcode::SUB,
+                vec![
+                    Operand::FieldEncoding(FieldEncoding::Reg(Register::CX), None),
+                    Operand::RawData(RawData::I16(-12), None),
+                ],
+            ),
+            Instruction::new(
+                Opcode::OR,
+                vec![
+                    Operand::FieldEncoding(
+                        FieldEncoding::Indexed(Register::BX, None, None),
+                        Some(ExplicitSize::Byte),
+                    ),
+                    Operand::RawData(RawData::U8(253), None),
+                ],
+            ),
+        ];
+        let mut p = Decoder::from(fake_instruction_stream);
+
+        p.decode().unwrap();
+
+        assert_eq!(expected, p.intermediate_repr);
+        assert_eq!(
+            "add word [bp + si + 1000], -3",
+            p.intermediate_repr[0].to_string()
+        );
+    }
+
+    #[test]
+    fn segment_registers() {
+        // mov ds, ax                 mov [bp + 2], es                    push cs     pop ds
+        // 10001110 11011000          10001100 01000110 00000010          00001110    00011111
+        let fake_instruction_stream: Vec<u8> = vec![142, 216, 140, 70, 2, 14, 31];
+        let expected = vec![
+            Instruction::new(
+                Opcode::MOV,
+                vec![
+                    Operand::FieldEncoding(FieldEncoding::Reg(Register::DS), None),
+                    Operand::FieldEncoding(FieldEncoding::Reg(Register::AX), None),
+                ],
+            ),
+            Instruction::new(
+                Opcode::MOV,
+                vec![
+                    Operand::FieldEncoding(
+                        FieldEncoding::Indexed(Register::BP, None, Some(2)),
+                        None,
+                    ),
+                    Operand::FieldEncoding(FieldEncoding::Reg(Register::ES), None),
+                ],
+            ),
+            Instruction::new(
+                Opcode::PUSH,
+                vec![Operand::FieldEncoding(
+                    FieldEncoding::Reg(Register::CS),
+                    None,
+                )],
+            ),
+            Instruction::new(
+                Opcode::POP,
+                vec![Operand::FieldEncoding(
+                    FieldEncoding::Reg(Register::DS),
+                    None,
+                )],
+            ),
+        ];
+        let mut p = Decoder::from(fake_instruction_stream);
+
+        p.decode().unwrap();
+
+        assert_eq!(expected, p.intermediate_repr);
+        assert_eq!("mov [bp + 2], es", p.intermediate_repr[1].to_string());
+        assert_eq!("pop ds", p.intermediate_repr[3].to_string());
+    }
+
+    #[test]
+    fn prefixes() {
+        // mov ax, es:[bx + si]                lock add [bx], ax
+        // 00100110 10001011 00000000          11110000 00000001 00000111
+        // add byte cs:[bx], 34
+        // 00101110 10000000 00000111 00100010
+        let fake_instruction_stream: Vec<u8> = vec![38, 139, 0, 240, 1, 7, 46, 128, 7, 34];
+        let mut p = Decoder::from(fake_instruction_stream);
+
+        p.decode().unwrap();
+
+        assert_eq!(3, p.intermediate_repr.len());
+        assert_eq!(
+            Prefixes {
+                segment: Some(Register::ES),
+                lock: false,
+                repeat: None,
+            },
+            p.intermediate_repr[0].prefixes
+        );
+        assert_eq!("mov ax, es:[bx + si]", p.intermediate_repr[0].to_string());
+        assert_eq!("lock add [bx], ax", p.intermediate_repr[1].to_string());
+        assert_eq!("add byte cs:[bx], 34", p.intermediate_repr[2].to_string());
+    }
+
+    #[test]
+    fn string_instructions() {
+        // rep movsb         repne scasb       movsw    lodsw
+        // 11110011 10100100 11110010 10101110 10100101 10101101
+        let fake_instruction_stream: Vec<u8> = vec![243, 164, 242, 174, 165, 173];
+        let mut p = Decoder::from(fake_instruction_stream);
+
+        p.decode().unwrap();
+
+        let listing: Vec<String> = p.intermediate_repr.iter().map(|i| i.to_string()).collect();
+        assert_eq!(vec!["rep movsb", "repne scasb", "movsw", "lodsw"], listing);
+
+        // cs movsb          rep es lodsw               mov ax, cs:[bx]
+        // 00101110 10100100 11110011 00100110 10101101 00101110 10001011 00000111
+        let mut p = Decoder::from(vec![46, 164, 243, 38, 173, 46, 139, 7]);
+        p.decode().unwrap();
+
+        let listing: Vec<String> = p.intermediate_repr.iter().map(|i| i.to_string()).collect();
+        assert_eq!(vec!["cs movsb", "rep es lodsw", "mov ax, cs:[bx]"], listing);
+    }
+
+    #[test]
+    fn string_instruction_execution() {
+        let mut rep_movsb = Instruction::new(Opcode::MOVSB, vec![]);
+        rep_movsb.prefixes.repeat = Some(Repeat::Rep);
+        let mut repne_scasb = Instruction::new(Opcode::SCASB, vec![]);
+        repne_scasb.prefixes.repeat = Some(Repeat::Repne);
+        let lodsw = Instruction::new(Opcode::LODSW, vec![]);
+
+        let mut cpu = Cpu::new();
+        cpu.ds = 0x1000;
+        cpu.es = 0x2000;
+        cpu.di = 0x10;
+        cpu.cx = 3;
+        for (offset, byte) in b"abcx".iter().enumerate() {
+            cpu.write_byte(cpu.ds, offset as u16, *byte);
+        }
+
+        cpu.execute(&rep_movsb).unwrap();
+        assert_eq!((3, 0x13, 0), (cpu.si, cpu.di, cpu.cx));
+        assert_eq!(b"abc", &cpu.memory.as_slice()[0x20010..0x20013]);
+
+        // scan the source for 'x', stops right after the match
+        cpu.es = cpu.ds;
+        cpu.di = 0;
+        cpu.cx = 10;
+        cpu.ax = b'x' as u16;
+        cpu.execute(&repne_scasb).unwrap();
+        assert_eq!((4, 6), (cpu.di, cpu.cx));
+        assert!(cpu.get_flag(ZF));
+
+        // with the direction flag set the index registers count down
+        cpu.set_flag(DF, true);
+        cpu.si = 2;
+        cpu.execute(&lodsw).unwrap();
+        assert_eq!(u16::from_le_bytes([b'c', b'x']), cpu.ax);
+        assert_eq!(0, cpu.si);
+    }
+
+    #[test]
+    fn variable_operand_count() {
+        let none = Instruction::new(Opcode::CMPSW, vec![]);
+        let one = Instruction::new(
+            Opcode::PUSH,
+            vec![Operand::FieldEncoding(
+                FieldEncoding::Reg(Register::ES),
+                None,
+            )],
+        );
+        let two = Instruction::new(
+            Opcode::MOV,
+            vec![
+                Operand::FieldEncoding(FieldEncoding::Direct(16), Some(ExplicitSize::Word)),
+                Operand::RawData(RawData::U16(4), None),
+            ],
+        );
+
+        assert_eq!("cmpsw", none.to_string());
+        assert_eq!("push es", one.to_string());
+        assert_eq!("mov word [16], 4", two.to_string());
+        assert_ne!(one, Instruction::new(Opcode::PUSH, vec![]));
+    }
+
+    #[test]
+    fn decode_errors() {
+        // mov si, bx                 pop cs (not an instruction)
+        // 10001001 11011110          00001111
+        let mut p = Decoder::from(vec![137, 222, 15]);
+        match p.decode() {
+            Err(DecodeError::UnknownOpcode { offset, byte }) => assert_eq!((2, 15), (offset, byte)),
+            other => panic!("expected an unknown opcode, got {:?}", other),
+        }
+        assert_eq!(1, p.intermediate_repr.len());
+
+        // add word [bp + si + 1000], 29 missing the high byte of the immediate
+        let mut p = Decoder::from(vec![129, 130, 232, 3, 29]);
+        match p.decode() {
+            Err(DecodeError::TruncatedInstruction { offset }) => assert_eq!(5, offset),
+            other => panic!("expected a truncated instruction, got {:?}", other),
+        }
+        assert!(p.intermediate_repr.is_empty());
+
+        let mut p = Decoder::new();
+        assert!(matches!(
+            p.load("this_listing_does_not_exist"),
+            Err(DecodeError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn instruction_location() {
+        // mov cx, bx        mov ax, es:[bx + si]        jne 0x0000
+        // 10001001 11011001 00100110 10001011 00000000  01110101 11111001
+        let mut p = Decoder::from(vec![137, 217, 38, 139, 0, 117, 249]);
+
+        p.decode().unwrap();
+
+        let addresses: Vec<usize> = p.intermediate_repr.iter().map(|i| i.address).collect();
+        assert_eq!(vec![0, 2, 5], addresses);
+        assert_eq!(vec![38, 139, 0], p.intermediate_repr[1].bytes);
+        assert_eq!(
+            "0000: 89 D9                      mov cx, bx",
+            p.intermediate_repr[0].listing()
+        );
+        // the mnemonic lines up behind the longest encodings too
+        // lock rep mov word ds:[16], 4660
+        // 11110000 11110011 00111110 11000111 00000110 00010000 00000000 00110100 00010010
+        let (instruction, _) =
+            Decoder::decode_at(&[240, 243, 62, 199, 6, 16, 0, 52, 18], 0).unwrap();
+        assert_eq!(
+            "0000: F0 F3 3E C7 06 10 00 34 12 lock rep mov word ds:[16], 4660",
+            instruction.listing()
+        );
+        assert_eq!(Some(0), p.intermediate_repr[2].jump_target());
+        assert_eq!(None, p.intermediate_repr[0].jump_target());
+        assert_eq!(Opcode::MOV, p.instruction_at(2).unwrap().opcode);
+        assert!(p.instruction_at(3).is_none());
+    }
+
+    #[test]
+    fn data_transfer() {
+        // push word [bp + si + 4]    pop word [bx]              push cx  pop di   xchg ax, dx
+        // 11111111 01110010 00000100 10001111 00000111          01010001 01011111 10010010
+        // xchg cl, [bx]              lea bx, [bp + si + 4]      lds si, [bx]
+        // 10000110 00001111          10001101 01011010 00000100 11000101 00110111
+        // les di, [1234]                      xlat     lahf     sahf     pushf    popf
+        // 11000100 00111110 11010010 00000100 11010111 10011111 10011110 10011100 10011101
+        let fake_instruction_stream: Vec<u8> = vec![
+            255, 114, 4, 143, 7, 81, 95, 146, 134, 15, 141, 90, 4, 197, 55, 196, 62, 210, 4, 215,
+            159, 158, 156, 157,
+        ];
+        let mut p = Decoder::from(fake_instruction_stream);
+
+        p.decode().unwrap();
+
+        assert_eq!(
+            Instruction::new(
+                Opcode::PUSH,
+                vec![Operand::FieldEncoding(
+                    FieldEncoding::Indexed(Register::BP, Some(Register::SI), Some(4)),
+                    Some(ExplicitSize::Word),
+                )],
+            ),
+            p.intermediate_repr[0]
+        );
+        let listing: Vec<String> = p.intermediate_repr.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            vec![
+                "push word [bp + si + 4]",
+                "pop word [bx]",
+                "push cx",
+                "pop di",
+                "xchg ax, dx",
+                "xchg cl, [bx]",
+                "lea bx, [bp + si + 4]",
+                "lds si, [bx]",
+                "les di, [1234]",
+                "xlat",
+                "lahf",
+                "sahf",
+                "pushf",
+                "popf",
+            ],
+            listing
+        );
+
+        // lea, lds and les take the address of a memory operand, a register has none
+        // 10001101 11000011 11000101 11000000 11000100 11111001
+        for bytes in [[141, 195], [197, 192], [196, 249]] {
+            match Decoder::decode_at(&bytes, 0) {
+                Err(DecodeError::UnknownOpcode { offset, byte }) => {
+                    assert_eq!((0, bytes[0]), (offset, byte))
+                }
+                other => panic!("expected {:?} to be rejected, got {:?}", bytes, other),
+            }
+        }
+    }
+
+    #[test]
+    fn port_io() {
+        // in al, 96         in ax, dx  out 67, al        out dx, ax
+        // 11100100 01100000 11101101   11100110 01000011 11101111
+        let mut p = Decoder::from(vec![228, 96, 237, 230, 67, 239]);
+
+        p.decode().unwrap();
+
+        let listing: Vec<String> = p.intermediate_repr.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            vec!["in al, 96", "in ax, dx", "out 67, al", "out dx, ax"],
+            listing
+        );
+
+        // echoes the port number back and remembers every write
+        struct Echo(std::rc::Rc<std::cell::RefCell<Vec<(u16, u16, bool)>>>);
+        impl PortHandler for Echo {
+            fn read(&mut self, port: u16, _word: bool) -> u16 {
+                port
+            }
+            fn write(&mut self, port: u16, value: u16, word: bool) {
+                self.0.borrow_mut().push((port, value, word));
+            }
+        }
+        let writes = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+        let mut cpu = Cpu::new();
+        cpu.ports = Box::new(Echo(writes.clone()));
+        cpu.ax = 0x1200;
+        cpu.dx = 0x3DA;
+
+        cpu.execute(&p.intermediate_repr[0]).unwrap();
+        assert_eq!(0x1260, cpu.ax);
+        cpu.execute(&p.intermediate_repr[1]).unwrap();
+        assert_eq!(0x3DA, cpu.ax);
+        cpu.execute(&p.intermediate_repr[2]).unwrap();
+        cpu.execute(&p.intermediate_repr[3]).unwrap();
+        assert_eq!(
+            vec![(67, 0xDA, false), (0x3DA, 0x3DA, true)],
+            *writes.borrow()
+        );
+
+        // with nothing attached the bus reads as all ones
+        cpu.ports = Box::new(NoPorts);
+        cpu.execute(&p.intermediate_repr[1]).unwrap();
+        assert_eq!(0xFFFF, cpu.ax);
+    }
+
+    #[test]
+    fn unary_multiply_divide() {
+        // test byte [bp + si + 1000], 7                        test ax, 1000
+        // 11110110 10000010 11101000 00000011 00000111         11110111 11000000 11101000 00000011
+        // not word [bx]              neg cl                    mul byte [1234]
+        // 11110111 00010111          11110110 11011001         11110110 00100110 11010010 00000100
+        // imul dx    div word [bp - 2]                  idiv bl
+        // 11110111 11101010          11110111 01110110 11111110          11110110 11111011
+        let fake_instruction_stream: Vec<u8> = vec![
+            246, 130, 232, 3, 7, 247, 192, 232, 3, 247, 23, 246, 217, 246, 38, 210, 4, 247, 234,
+            247, 118, 254, 246, 251,
+        ];
+        let mut p = Decoder::from(fake_instruction_stream);
+
+        p.decode().unwrap();
+
+        assert_eq!(
+            Instruction::new(
+                Opcode::TEST,
+                vec![
+                    Operand::FieldEncoding(
+                        FieldEncoding::Indexed(Register::BP, Some(Register::SI), Some(1000)),
+                        Some(ExplicitSize::Byte),
+                    ),
+                    Operand::RawData(RawData::U8(7), None),
+                ],
+            ),
+            p.intermediate_repr[0]
+        );
+        let listing: Vec<String> = p.intermediate_repr.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            vec![
+                "test byte [bp + si + 1000], 7",
+                "test ax, 1000",
+                "not word [bx]",
+                "neg cl",
+                "mul byte [1234]",
+                "imul dx",
+                "div word [bp - 2]",
+                "idiv bl",
+            ],
+            listing
+        );
+
+        // reg = 001 is not an instruction in this group
+        let mut p = Decoder::from(vec![246, 200]);
+        assert!(matches!(
+            p.decode(),
+            Err(DecodeError::UnknownOpcode {
+                offset: 0,
+                byte: 246
+            })
+        ));
+    }
+
+    #[test]
+    fn shifts_and_rotates() {
+        // shl ax, 1         sar word [bx + 2], cl       rcr bl, 1         ror dh, cl
+        // 11010001 11100000 11010011 01111111 00000010  11010000 11011011 11010010 11001110
+        let mut p = Decoder::from(vec![209, 224, 211, 127, 2, 208, 219, 210, 206]);
+
+        p.decode().unwrap();
+
+        let listing: Vec<String> = p.intermediate_repr.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            vec![
+                "shl ax, 1",
+                "sar word [bx + 2], cl",
+                "rcr bl, 1",
+                "ror dh, cl"
+            ],
+            listing
+        );
+    }
+
+    #[test]
+    fn shift_and_rotate_execution() {
+        let shift = |opcode: Opcode, register: Register, count: u8| {
+            Instruction::new(
+                opcode,
+                vec![
+                    Operand::FieldEncoding(FieldEncoding::Reg(register), None),
+                    Operand::RawData(RawData::U8(count), None),
+                ],
+            )
         };
-        let mut p = Decoder {
-            intermediate_repr: VecDeque::new().into(),
-            instruction_queue: fake_instruction_stream.into(),
+        let mut cpu = Cpu::new();
+
+        cpu.ax = 0xC001;
+        cpu.execute(&shift(Opcode::SHL, Register::AX, 1)).unwrap();
+        assert_eq!(0x8002, cpu.ax);
+        assert!(cpu.get_flag(CF) && !cpu.get_flag(OF) && cpu.get_flag(SF));
+
+        cpu.ax = 0x4000;
+        cpu.execute(&shift(Opcode::SHL, Register::AX, 1)).unwrap();
+        assert!(!cpu.get_flag(CF) && cpu.get_flag(OF));
+
+        // the count isn't masked to 5 bits, 33 shifts clear a register
+        cpu.ax = 0xFFFF;
+        cpu.execute(&shift(Opcode::SHR, Register::AX, 33)).unwrap();
+        assert_eq!(0, cpu.ax);
+        assert!(cpu.get_flag(ZF) && !cpu.get_flag(CF));
+
+        cpu.set_register(&Register::BL, 0x81);
+        cpu.execute(&shift(Opcode::SAR, Register::BL, 1)).unwrap();
+        assert_eq!(0xC0, cpu.get_register(&Register::BL));
+        assert!(cpu.get_flag(CF) && !cpu.get_flag(OF));
+
+        // rotating through carry takes nine steps to come back around on a byte
+        cpu.set_flag(CF, false);
+        cpu.set_register(&Register::CL, 0x5A);
+        cpu.execute(&shift(Opcode::RCL, Register::CL, 9)).unwrap();
+        assert_eq!(0x5A, cpu.get_register(&Register::CL));
+        assert!(!cpu.get_flag(CF));
+
+        cpu.set_register(&Register::DH, 0x01);
+        cpu.execute(&shift(Opcode::ROR, Register::DH, 1)).unwrap();
+        assert_eq!(0x80, cpu.get_register(&Register::DH));
+        assert!(cpu.get_flag(CF) && cpu.get_flag(OF));
+    }
+
+    #[test]
+    fn inc_dec_and_indirect_branches() {
+        // inc ax   dec si   inc byte [bx]     dec word [bp + 4]           dec cl
+        // 01000000 01001110 11111110 00000111 11111111 01001110 00000100  11111110 11001001
+        // call [bp - 2]                       call far [bx]      jmp di            jmp far [1234]
+        // 11111111 01010110 11111110          11111111 00011111  11111111 11100111 11111111 00101110 11010010 00000100
+        let fake_instruction_stream: Vec<u8> = vec![
+            64, 78, 254, 7, 255, 78, 4, 254, 201, 255, 86, 254, 255, 31, 255, 231, 255, 46, 210, 4,
+        ];
+        let mut p = Decoder::from(fake_instruction_stream);
+
+        p.decode().unwrap();
+
+        assert_eq!(
+            Instruction::new(
+                Opcode::CALL,
+                vec![Operand::FieldEncoding(
+                    FieldEncoding::Indexed(Register::BX, None, None),
+                    Some(ExplicitSize::Far),
+                )],
+            ),
+            p.intermediate_repr[6]
+        );
+        let listing: Vec<String> = p.intermediate_repr.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            vec![
+                "inc ax",
+                "dec si",
+                "inc byte [bx]",
+                "dec word [bp + 4]",
+                "dec cl",
+                "call word [bp - 2]",
+                "call far [bx]",
+                "jmp di",
+                "jmp far [1234]",
+            ],
+            listing
+        );
+
+        // call far and jmp far need a pointer in memory, not a register
+        // 11111111 11011000 11111111 11101000
+        for bytes in [[255, 216], [255, 232]] {
+            assert!(matches!(
+                Decoder::decode_at(&bytes, 0),
+                Err(DecodeError::UnknownOpcode {
+                    offset: 0,
+                    byte: 255
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn direct_calls_jumps_and_returns() {
+        // call 0x0003                jmp 0x0000                 jmp 0x0003
+        // 11101000 00000000 00000000 11101001 11111010 11111111 11101011 11111011
+        // call 0x1234:0x0010                           jmp 0xf000:0xfff0
+        // 10011010 00010000 00000000 00110100 00010010 11101010 11110000 11111111 00000000 11110000
+        // ret      ret 4                      retf     retf 8
+        // 11000011 11000010 00000100 00000000 11001011 11001010 00001000 00000000
+        let fake_instruction_stream: Vec<u8> = vec![
+            232, 0, 0, 233, 250, 255, 235, 251, 154, 16, 0, 52, 18, 234, 240, 255, 0, 240, 195,
+            194, 4, 0, 203, 202, 8, 0,
+        ];
+        let mut p = Decoder::from(fake_instruction_stream);
+
+        p.decode().unwrap();
+
+        assert_eq!(
+            Instruction::new(Opcode::JMP, vec![Operand::Relative(-6)]),
+            p.intermediate_repr[1]
+        );
+        assert_eq!(
+            Instruction::new(Opcode::CALL, vec![Operand::FarPointer(0x1234, 0x0010)]),
+            p.intermediate_repr[3]
+        );
+        let listing: Vec<String> = p.intermediate_repr.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            vec![
+                "call 0x0003",
+                "jmp 0x0000",
+                "jmp 0x0003",
+                "call 0x1234:0x0010",
+                "jmp 0xf000:0xfff0",
+                "ret",
+                "ret 4",
+                "retf",
+                "retf 8",
+            ],
+            listing
+        );
+    }
+
+    #[test]
+    fn interrupt_and_processor_control() {
+        // int 33            int3     into     iret     hlt      wait     esc 63, [bx + 4]
+        // 11001101 00100001 11001100 11001110 11001111 11110100 10011011 11011111 01111111 00000100
+        // esc 10, dx        cmc      clc      stc      cli      sti      cld      std      nop
+        // 11011001 11010010 11110101 11111000 11111001 11111010 11111011 11111100 11111101 10010000
+        let fake_instruction_stream: Vec<u8> = vec![
+            205, 33, 204, 206, 207, 244, 155, 223, 127, 4, 217, 210, 245, 248, 249, 250, 251, 252,
+            253, 144,
+        ];
+        let mut p = Decoder::from(fake_instruction_stream);
+
+        p.decode().unwrap();
+
+        assert_eq!(
+            Instruction::new(
+                Opcode::ESC,
+                vec![
+                    Operand::RawData(RawData::U8(63), None),
+                    Operand::FieldEncoding(
+                        FieldEncoding::Indexed(Register::BX, None, Some(4)),
+                        None
+                    ),
+                ],
+            ),
+            p.intermediate_repr[6]
+        );
+        let listing: Vec<String> = p.intermediate_repr.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            vec![
+                "int 33",
+                "int3",
+                "into",
+                "iret",
+                "hlt",
+                "wait",
+                "esc 63, [bx + 4]",
+                "esc 10, dx",
+                "cmc",
+                "clc",
+                "stc",
+                "cli",
+                "sti",
+                "cld",
+                "std",
+                "nop",
+            ],
+            listing
+        );
+    }
+
+    #[test]
+    fn decimal_adjust_and_sign_extension() {
+        // daa      das      aaa      aas      aam               aad               cbw      cwd
+        // 00100111 00101111 00110111 00111111 11010100 00001010 11010101 00001010 10011000 10011001
+        let mut p = Decoder::from(vec![39, 47, 55, 63, 212, 10, 213, 10, 152, 153]);
+        p.decode().unwrap();
+
+        let listing: Vec<String> = p.intermediate_repr.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            vec!["daa", "das", "aaa", "aas", "aam 10", "aad 10", "cbw", "cwd"],
+            listing
+        );
+
+        let program = &p.intermediate_repr;
+        let mut cpu = Cpu::new();
+
+        // 79 + 35 = 114, adjusting both digits carries out
+        cpu.set_register(&Register::AL, 0xAE);
+        cpu.execute(&program[0]).unwrap();
+        assert_eq!(0x14, cpu.get_register(&Register::AL));
+        assert!(cpu.get_flag(CF) && cpu.get_flag(AF));
+
+        // 10 - 01 leaves a borrow out of the low digit
+        cpu.set_flag(CF, false);
+        cpu.set_flag(AF, true);
+        cpu.set_register(&Register::AL, 0x0F);
+        cpu.execute(&program[1]).unwrap();
+        assert_eq!(0x09, cpu.get_register(&Register::AL));
+        assert!(!cpu.get_flag(CF) && cpu.get_flag(AF));
+
+        // adjusting a low digit below 6 borrows out of al by itself
+        cpu.set_flag(CF, false);
+        cpu.set_flag(AF, true);
+        cpu.set_register(&Register::AL, 0x03);
+        cpu.execute(&program[1]).unwrap();
+        assert_eq!(0xFD, cpu.get_register(&Register::AL));
+        assert!(cpu.get_flag(CF) && cpu.get_flag(AF));
+
+        cpu.ax = 0x000F;
+        cpu.execute(&program[2]).unwrap();
+        assert_eq!(0x0105, cpu.ax);
+        assert!(cpu.get_flag(CF) && cpu.get_flag(AF));
+
+        cpu.ax = 0x0200;
+        cpu.set_flag(AF, true);
+        cpu.execute(&program[3]).unwrap();
+        assert_eq!(0x010A, cpu.ax);
+        assert!(cpu.get_flag(CF));
+
+        cpu.ax = 0x004F;
+        cpu.execute(&program[4]).unwrap();
+        assert_eq!(0x0709, cpu.ax);
+        assert!(!cpu.get_flag(ZF) && !cpu.get_flag(CF));
+
+        cpu.execute(&program[5]).unwrap();
+        assert_eq!(0x004F, cpu.ax);
+
+        cpu.ax = 0x0080;
+        cpu.execute(&program[6]).unwrap();
+        assert_eq!(0xFF80, cpu.ax);
+        cpu.execute(&program[7]).unwrap();
+        assert_eq!(0xFFFF, cpu.dx);
+
+        // aam 0 is a divide error and goes through vector 0
+        cpu.write_word(0, 0, 0x1234);
+        cpu.write_word(0, 2, 0x0100);
+        cpu.ss = 0x2000;
+        cpu.sp = 0x0100;
+        cpu.ip = 0x0006;
+        cpu.set_flag(IF, true);
+        cpu.execute(&Instruction::new(
+            Opcode::AAM,
+            vec![Operand::RawData(RawData::U8(0), None)],
+        ))
+        .unwrap();
+        assert_eq!((0x0100, 0x1234), (cpu.cs, cpu.ip));
+        assert_eq!(0x00FA, cpu.sp);
+        assert_eq!(0x0006, cpu.pop());
+        assert!(!cpu.get_flag(IF));
+    }
+
+    #[test]
+    fn opcode_table() {
+        use crate::opcode_table::{Mnemonic, OPCODE_TABLE};
+
+        // only pop cs and the undocumented aliases are left out
+        let invalid: Vec<usize> = (0..256)
+            .filter(|&byte| matches!(OPCODE_TABLE[byte].mnemonic, Mnemonic::Invalid))
+            .collect();
+        let mut expected = vec![0x0F, 0xC0, 0xC1, 0xC8, 0xC9, 0xD6, 0xF1];
+        expected.extend(0x60..0x70);
+        expected.sort();
+        assert_eq!(expected, invalid);
+
+        // every row and group member matches its operands, whatever follows the opcode it either
+        // decodes or is rejected as an unknown opcode
+        for byte in 0..=255u8 {
+            if let Mnemonic::Prefix = OPCODE_TABLE[byte as usize].mnemonic {
+                continue;
+            }
+            for second_byte in 0..=255u8 {
+                match Decoder::decode_at(&[byte, second_byte, 0, 0, 0, 0], 0) {
+                    Ok(_) | Err(DecodeError::UnknownOpcode { offset: 0, .. }) => (),
+                    Err(err) => panic!("{:02X} {:02X}: {}", byte, second_byte, err),
+                }
+            }
+        }
+
+        // mov byte [bp + si + 4], 7   mov word [bx], 300                  test [bx], cl
+        // 11000110 01000010 00000100 00000111 11000111 00000111 00101100 00000001 10000100 00001111
+        // test al, 5        test ax, 513
+        // 10101000 00000101 10101001 00000001 00000010
+        let mut p = Decoder::from(vec![
+            198, 66, 4, 7, 199, 7, 44, 1, 132, 15, 168, 5, 169, 1, 2,
+        ]);
+        p.decode().unwrap();
+
+        assert_eq!(
+            Instruction::new(
+                Opcode::MOV,
+                vec![
+                    Operand::FieldEncoding(
+                        FieldEncoding::Indexed(Register::BP, Some(Register::SI), Some(4)),
+                        Some(ExplicitSize::Byte)
+                    ),
+                    Operand::RawData(RawData::U8(7), None),
+                ],
+            ),
+            p.intermediate_repr[0]
+        );
+        let listing: Vec<String> = p.intermediate_repr.iter().map(|i| i.to_string()).collect();
+        assert_eq!(
+            vec![
+                "mov byte [bp + si + 4], 7",
+                "mov word [bx], 300",
+                "test [bx], cl",
+                "test al, 5",
+                "test ax, 513",
+            ],
+            listing
+        );
+
+        // c6 with a reg field other than 0 isn't an instruction
+        let mut p = Decoder::from(vec![198, 8, 1]);
+        assert!(matches!(
+            p.decode(),
+            Err(DecodeError::UnknownOpcode {
+                offset: 0,
+                byte: 198
+            })
+        ));
+    }
+
+    #[test]
+    fn decode_at_offset() {
+        // mov cx, bx        es: mov [bp], al           jne -4
+        // 10001001 11011001 00100110 10001000 01000110 00000000 01110101 11111100
+        let image: Vec<u8> = vec![137, 217, 38, 136, 70, 0, 117, 252];
+
+        let (instruction, length) = Decoder::decode_at(&image, 2).unwrap();
+        assert_eq!(4, length);
+        assert_eq!(2, instruction.address);
+        assert_eq!(vec![38, 136, 70, 0], instruction.bytes);
+        assert_eq!("mov es:[bp], al", instruction.to_string());
+
+        // the jump resolves against where it was decoded from
+        let (instruction, length) = Decoder::decode_at(&image, 6).unwrap();
+        assert_eq!(2, length);
+        assert_eq!("jne 0x0004", instruction.to_string());
+
+        assert!(matches!(
+            Decoder::decode_at(&image[..5], 2),
+            Err(DecodeError::TruncatedInstruction { offset: 5 })
+        ));
+
+        // decoding leaves the image alone, so it can be done again
+        let mut p = Decoder::from(image);
+        p.decode().unwrap();
+        p.decode().unwrap();
+        assert_eq!(3, p.intermediate_repr.len());
+        assert_eq!(8, p.image.len());
+    }
+
+    #[test]
+    fn lazy_instructions() {
+        // mov cx, bx        inc ax   jne -3            nop      (unknown)
+        // 10001001 11011001 01000000 01110101 11111101 10010000 11110001 10010000
+        let image: Vec<u8> = vec![137, 217, 64, 117, 253, 144, 241, 144];
+
+        let p = Decoder::from(image.clone());
+        let first: Vec<String> = p
+            .instructions()
+            .take(2)
+            .map(|i| i.unwrap().to_string())
+            .collect();
+        assert_eq!(vec!["mov cx, bx", "inc ax"], first);
+
+        let jumps: Vec<Instruction> = Instructions::new(&image, 2)
+            .map_while(Result::ok)
+            .filter(|i| i.jump_target().is_some())
+            .collect();
+        assert_eq!(1, jumps.len());
+        assert_eq!(3, jumps[0].address);
+
+        // the error ends the iteration, and the offset says where it happened
+        let mut instructions = p.instructions();
+        assert_eq!(4, instructions.by_ref().take_while(Result::is_ok).count());
+        assert_eq!(6, instructions.offset());
+        assert!(instructions.next().is_none());
+    }
+
+    #[test]
+    fn register_execution() {
+        // mov cx, 3                  mov bx, 1000
+        // 10111001 00000011 00000000 10111011 11101000 00000011
+        // label:
+        // add bx, 10                 sub cx, 1                  jnz label
+        // 10000011 11000011 00001010 10000011 11101001 00000001 01110101 11111000
+        // cmp bx, 1030                        hlt      mov dx, 1
+        // 10000001 11111011 00000110 00000100 11110100 10111010 00000001 00000000
+        let image: Vec<u8> = vec![
+            185, 3, 0, 187, 232, 3, 131, 195, 10, 131, 233, 1, 117, 248, 129, 251, 6, 4, 244, 186,
+            1, 0,
+        ];
+        let mut cpu = Cpu::new();
+        cpu.load(&image, 0, 0);
+        cpu.run().unwrap();
+
+        assert_eq!((1030, 0, 0), (cpu.bx, cpu.cx, cpu.dx));
+        assert_eq!(19, cpu.ip);
+        assert!(cpu.halted);
+        assert!(cpu.get_flag(ZF) && cpu.get_flag(PF) && !cpu.get_flag(CF));
+
+        // add al, -30       sub al, ah        cmp al, 9
+        // 00000100 11100010 00101010 11000100 00111100 00001001
+        let image: Vec<u8> = vec![4, 226, 42, 196, 60, 9];
+        let mut cpu = Cpu::new();
+        cpu.load(&image, 0, 0);
+        cpu.ax = 0x0110;
+        cpu.step().unwrap();
+        assert_eq!(0x01F2, cpu.ax);
+        assert!(cpu.get_flag(SF) && !cpu.get_flag(CF) && !cpu.get_flag(OF));
+        cpu.step().unwrap();
+        assert_eq!(0x01F1, cpu.ax);
+        assert!(!cpu.get_flag(CF) && cpu.get_flag(SF));
+        cpu.step().unwrap();
+        assert_eq!(0x01F1, cpu.ax);
+        assert!(!cpu.get_flag(ZF) && !cpu.get_flag(CF) && cpu.get_flag(SF));
+        assert_eq!(6, cpu.ip);
+
+        // loop counts cx down, jcxz then falls through to the end
+        // loop -2           jcxz 0
+        // 11100010 11111110 11100011 00000000
+        let image: Vec<u8> = vec![226, 254, 227, 0];
+        let mut cpu = Cpu::new();
+        cpu.cx = 5;
+        cpu.load(&image, 0, 0);
+        cpu.run().unwrap();
+        assert_eq!((0, 4), (cpu.cx, cpu.ip));
+    }
+
+    #[test]
+    fn flag_control_and_stack_execution() {
+        // nop      hlt
+        // 10010000 11110100
+        let mut cpu = Cpu::new();
+        cpu.load(&[144, 244], 0, 0);
+        cpu.run().unwrap();
+        assert_eq!(2, cpu.ip);
+        assert!(cpu.halted);
+
+        // cld      hlt
+        // 11111100 11110100
+        let mut cpu = Cpu::new();
+        cpu.load(&[252, 244], 0, 0);
+        cpu.set_flag(DF, true);
+        cpu.run().unwrap();
+        assert!(!cpu.get_flag(DF));
+
+        // stc      cmc      std      sti      hlt
+        // 11111001 11110101 11111101 11111011 11110100
+        let mut cpu = Cpu::new();
+        cpu.load(&[249, 245, 253, 251, 244], 0, 0);
+        cpu.run().unwrap();
+        assert_eq!(DF | IF, cpu.flags);
+
+        // push ax  pop ds   push sp  pop bx   hlt
+        // 01010000 00011111 01010100 01011011 11110100
+        let mut cpu = Cpu::new();
+        cpu.load(&[80, 31, 84, 91, 244], 0, 0);
+        cpu.ss = 0x2000;
+        cpu.sp = 0x100;
+        cpu.ax = 0x1234;
+        cpu.step().unwrap();
+        assert_eq!((0xFE, 0x1234), (cpu.sp, cpu.read_word(0x2000, 0xFE)));
+        cpu.run().unwrap();
+        // sp is pushed as it is after the decrement
+        assert_eq!((0x1234, 0xFE, 0x100), (cpu.ds, cpu.bx, cpu.sp));
+
+        // bits 1 and 12-15 push as set, popf only keeps the flags
+        // pushf    popf     pushf    hlt
+        // 10011100 10011101 10011100 11110100
+        let mut cpu = Cpu::new();
+        cpu.load(&[156, 157, 156, 244], 0, 0);
+        cpu.ss = 0x2000;
+        cpu.sp = 0x100;
+        cpu.step().unwrap();
+        assert_eq!(0xF002, cpu.read_word(0x2000, 0xFE));
+        cpu.write_word(0x2000, 0xFE, 0xFFFF);
+        cpu.run().unwrap();
+        assert_eq!(CF | PF | AF | ZF | SF | TF | IF | DF | OF, cpu.flags);
+        assert_eq!(0xFFD7, cpu.read_word(0x2000, 0xFE));
+
+        // mul bl            hlt
+        // 11110110 11100011 11110100
+        let mut cpu = Cpu::new();
+        cpu.load(&[246, 227, 244], 0, 0);
+        match cpu.run() {
+            Err(ExecError::Unsupported(instruction)) => {
+                assert_eq!("mul bl", instruction.to_string())
+            }
+            other => panic!("expected mul bl to be unsupported, got {:?}", other),
+        }
+        // ip is left on the instruction
+        assert_eq!(0, cpu.ip);
+        assert!(cpu.is_running());
+    }
+
+    #[test]
+    fn arithmetic_and_logic_flags() {
+        let alu = |opcode, flags, a, b, word| crate::flags::alu(opcode, flags, a, b, word).unwrap();
+
+        let all = CF | PF | AF | ZF | SF | OF;
+        assert_eq!(
+            (0x00, CF | PF | AF | ZF),
+            alu(Opcode::ADD, 0, 0xFF, 1, false)
+        );
+        assert_eq!((0x80, AF | SF | OF), alu(Opcode::ADD, 0, 0x7F, 1, false));
+        assert_eq!(
+            (0x0000, CF | PF | AF | ZF),
+            alu(Opcode::ADC, CF, 0xFFFF, 0, true)
+        );
+        assert_eq!((0xFF, CF | PF | AF | SF), alu(Opcode::SBB, CF, 0, 0, false));
+        assert_eq!((0x7F, AF | OF), alu(Opcode::SUB, 0, 0x80, 1, false));
+        // cmp gives the difference back, the caller doesn't store it
+        assert_eq!((0x0000, PF | ZF), alu(Opcode::CMP, 0, 0x1234, 0x1234, true));
+
+        // neg sets the carry for anything but zero
+        assert_eq!((0x00, PF | ZF), alu(Opcode::NEG, CF, 0, 0, false));
+        assert_eq!((0xFFFF, CF | PF | AF | SF), alu(Opcode::NEG, 0, 1, 0, true));
+        assert_eq!((0x80, CF | SF | OF), alu(Opcode::NEG, 0, 0x80, 0, false));
+
+        // inc and dec keep whatever carry was there
+        assert_eq!(
+            (0x00, CF | PF | AF | ZF),
+            alu(Opcode::INC, CF, 0xFF, 0, false)
+        );
+        assert_eq!((0xFFFF, PF | AF | SF), alu(Opcode::DEC, 0, 0, 0, true));
+        assert_eq!(
+            (0x8000, PF | AF | SF | OF),
+            alu(Opcode::INC, 0, 0x7FFF, 0, true)
+        );
+
+        // logic ops clear CF, OF and AF
+        assert_eq!((0x0F, PF), alu(Opcode::AND, all, 0x3F, 0xCF, false));
+        assert_eq!(
+            (0x8000, SF | PF),
+            alu(Opcode::TEST, all, 0x8000, 0xFFFF, true)
+        );
+        assert_eq!((0x00, PF | ZF), alu(Opcode::XOR, all, 0x5A, 0x5A, false));
+        assert_eq!((0x01, 0), alu(Opcode::OR, all & !PF, 0x01, 0x00, false));
+        assert_eq!((0xA5, all), alu(Opcode::NOT, all, 0x5A, 0, false));
+
+        // executed, a byte register keeps its other half
+        // adc bh, 1                  neg dl            dec cx
+        // 10000000 11010111 00000001 11110110 11011010 01001001
+        let image: Vec<u8> = vec![128, 215, 1, 246, 218, 73];
+        let mut cpu = Cpu::new();
+        cpu.bx = 0xFF34;
+        cpu.dx = 0x1201;
+        cpu.set_flag(CF, true);
+        cpu.load(&image, 0, 0);
+        cpu.run().unwrap();
+        assert_eq!(0x0134, cpu.bx);
+        assert_eq!(0x12FF, cpu.dx);
+        assert_eq!(0xFFFF, cpu.cx);
+        // dec kept the carry neg left behind
+        assert!(cpu.get_flag(CF) && cpu.get_flag(SF));
+    }
+
+    #[test]
+    fn segmented_memory() {
+        use crate::memory::Memory;
+
+        let mut memory = Memory::new();
+        assert_eq!(0x12350, Memory::physical_address(0x1234, 0x0010));
+        // ffff:0010 is past the end of the 20 bits and wraps to 0
+        assert_eq!(0x00000, Memory::physical_address(0xFFFF, 0x0010));
+        memory.write_byte(0xFFFF, 0x0015, 0xAB);
+        assert_eq!(0xAB, memory.as_slice()[0x00005]);
+        assert_eq!(0xAB, memory.read_byte(0x0000, 0x0005));
+
+        // a word at offset ffff wraps within the segment, not into the next paragraph
+        memory.write_word(0x2000, 0xFFFF, 0x1234);
+        assert_eq!(0x34, memory.read_byte(0x2000, 0xFFFF));
+        assert_eq!(0x12, memory.read_byte(0x2000, 0x0000));
+        assert_eq!(0x00, memory.as_slice()[0x30000]);
+        assert_eq!(0x1234, memory.read_word(0x2000, 0xFFFF));
+
+        // the same program runs wherever it's loaded
+        // mov cx, 3                  label: dec cx     jnz label         hlt
+        // 10111001 00000011 00000000 01001001          01110101 11111101 11110100
+        let image: Vec<u8> = vec![185, 3, 0, 73, 117, 253, 244];
+        let mut cpu = Cpu::new();
+        cpu.load(&image, 0x1000, 0x0100);
+        assert_eq!(0xB9, cpu.read_byte(0x1010, 0x0000));
+        assert!(cpu.is_running());
+        let first = cpu.step().unwrap();
+        assert_eq!(0x0100, first.address);
+        cpu.step().unwrap();
+        // addresses are offsets into cs, the jump prints the ip it goes to
+        let jump = cpu.step().unwrap();
+        assert_eq!("jne 0x0103", jump.to_string());
+        assert_eq!(Some(cpu.ip as usize), jump.jump_target());
+        cpu.run().unwrap();
+        assert!(cpu.halted && !cpu.is_running());
+        assert_eq!((0x1000, 0x0107, 0), (cpu.cs, cpu.ip, cpu.cx));
+
+        // jne $
+        // 01110101 11111110
+        let mut cpu = Cpu::new();
+        cpu.load(&[117, 254], 0x1010, 0x0000);
+        let jump = cpu.step().unwrap();
+        assert_eq!("jne 0x0000", jump.to_string());
+        assert_eq!((Some(0), 0), (jump.jump_target(), cpu.ip));
+
+        // instructions are fetched through cs, past offset ffff they continue at offset 0
+        // mov ax, 0x1234
+        // 10111000 00110100 00010010
+        let mut cpu = Cpu::new();
+        cpu.write_byte(0x1000, 0xFFFF, 0xB8);
+        cpu.write_word(0x1000, 0x0000, 0x1234);
+        (cpu.cs, cpu.ip) = (0x1000, 0xFFFF);
+        assert_eq!("mov ax, 4660", cpu.step().unwrap().to_string());
+        assert_eq!((0x1234, 0x0002), (cpu.ax, cpu.ip));
+
+        // and past the top of memory they continue at physical 0
+        let mut cpu = Cpu::new();
+        cpu.write_byte(0xFFFF, 0x000F, 0xB8);
+        cpu.write_word(0xFFFF, 0x0010, 0x1234);
+        assert_eq!(0x34, cpu.memory.as_slice()[0x00000]);
+        (cpu.cs, cpu.ip) = (0xFFFF, 0x000F);
+        cpu.step().unwrap();
+        assert_eq!((0x1234, 0x0012), (cpu.ax, cpu.ip));
+
+        // a program loaded across the top of memory runs to its end at physical 1
+        // nop      nop      nop      hlt
+        // 10010000 10010000 10010000 11110100
+        let mut cpu = Cpu::new();
+        cpu.load(&[144, 144, 144, 244], 0xFFFF, 0x000E);
+        assert_eq!(0xF4, cpu.memory.as_slice()[0x00001]);
+        cpu.run().unwrap();
+        assert!(cpu.halted);
+        assert_eq!(0x0012, cpu.ip);
+        assert!(!cpu.is_running());
+    }
+
+    #[test]
+    fn memory_operands() {
+        let mut cpu = Cpu::new();
+        (cpu.ds, cpu.ss, cpu.es) = (0x0100, 0x0200, 0x0300);
+        (cpu.bx, cpu.si, cpu.bp, cpu.di) = (0x0010, 0x0004, 0x0020, 0x0002);
+
+        let none = Prefixes::default();
+        let bx_si = FieldEncoding::Indexed(Register::BX, Some(Register::SI), Some(4));
+        let bp_di = FieldEncoding::Indexed(Register::BP, Some(Register::DI), Some(-2));
+        assert_eq!(Some((0x0100, 0x0018)), cpu.effective_address(&bx_si, &none));
+        assert_eq!(Some((0x0200, 0x0020)), cpu.effective_address(&bp_di, &none));
+        assert_eq!(
+            Some((0x0100, 0x0040)),
+            cpu.effective_address(&FieldEncoding::Direct(0x40), &none)
+        );
+        assert_eq!(
+            None,
+            cpu.effective_address(&FieldEncoding::Reg(Register::AX), &none)
+        );
+        let es = Prefixes {
+            segment: Some(Register::ES),
+            ..Prefixes::default()
         };
+        assert_eq!(Some((0x0300, 0x0020)), cpu.effective_address(&bp_di, &es));
+        // the offset wraps at 16 bits
+        cpu.bx = 0xFFFF;
+        assert_eq!(Some((0x0100, 0x0007)), cpu.effective_address(&bx_si, &none));
+        cpu.bx = 0x0010;
 
-        p.decode();
+        // mov [bx + si + 4], ax      mov [bp + di - 2], al      es: add [bp], cx
+        // 10001001 01000000 00000100 10001000 01000011 11111110 00100110 00000001 01001110 00000000
+        // add word [1000], 5                           mov dx, [bx + si + 4]      lea di, [bp + si + 6]
+        // 10000011 00000110 11101000 00000011 00000101 10001011 01010000 00000100 10001101 01111010 00000110
+        // hlt
+        // 11110100
+        let image: Vec<u8> = vec![
+            137, 64, 4, 136, 67, 254, 38, 1, 78, 0, 131, 6, 232, 3, 5, 139, 80, 4, 141, 122, 6, 244,
+        ];
+        cpu.load(&image, 0, 0);
+        cpu.ax = 0x1234;
+        cpu.cx = 0x00FF;
+        cpu.write_word(0x0300, 0x0020, 0x0001);
+        cpu.write_word(0x0100, 1000, 0xFFFE);
+        cpu.run().unwrap();
 
-        assert_eq!(expected_u16, *p.intermediate_repr.get(0).unwrap());
-        assert_eq!(expected_i8, *p.intermediate_repr.get(1).unwrap());
+        assert_eq!(0x1234, cpu.read_word(0x0100, 0x0018));
+        assert_eq!(0x34, cpu.read_byte(0x0200, 0x0020));
+        assert_eq!(0x00, cpu.read_byte(0x0200, 0x0021));
+        assert_eq!(0x0100, cpu.read_word(0x0300, 0x0020));
+        assert_eq!(0x0003, cpu.read_word(0x0100, 1000));
+        assert!(cpu.get_flag(CF));
+        assert_eq!(0x1234, cpu.dx);
+        assert_eq!(0x002A, cpu.di);
     }
 }
@@ -1,18 +1,43 @@
+use fake_cpu::cpu::Cpu;
 use fake_cpu::instruction_decode::*;
 use std::env;
-use std::fs;
+use std::process;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
     let file_name = &args[1];
 
-    let mut file_content = fs::read(file_name).expect("this should work");
-    file_content.reverse();
-
     let mut p = Decoder::new();
-    let _ = p.load(file_name);
+    if let Err(err) = p.load(file_name) {
+        eprintln!("{}: {}", file_name, err);
+        process::exit(1);
+    }
     p.dump_memory();
-    p.decode();
-    p.execute();
+    // --exec runs the image instead and prints the registers it ends with
+    if args.get(2).map(String::as_str) == Some("--exec") {
+        let mut cpu = Cpu::new();
+        cpu.load(&p.image, 0, 0);
+        while cpu.is_running() {
+            match cpu.step() {
+                Ok(instruction) => println!("{}", instruction),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                }
+            }
+        }
+        cpu.dump_registers();
+        return;
+    }
+    // instructions are printed as they're decoded, everything before an error still shows up
+    for instruction in p.instructions() {
+        match instruction {
+            Ok(instruction) => println!("{}", instruction),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
 }
@@ -0,0 +1,62 @@
+// the 1 MiB the 8086 can address with its 20 bit physical addresses
+pub const MEMORY_SIZE: usize = 1 << 20;
+
+pub struct Memory {
+    bytes: Vec<u8>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Memory {
+            bytes: vec![0; MEMORY_SIZE],
+        }
+    }
+
+    // segment * 16 + offset, past 0xfffff it wraps back around to 0
+    pub fn physical_address(segment: u16, offset: u16) -> usize {
+        (((segment as usize) << 4) + offset as usize) & (MEMORY_SIZE - 1)
+    }
+
+    // the whole address space by physical address
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn read_byte(&self, segment: u16, offset: u16) -> u8 {
+        self.bytes[Memory::physical_address(segment, offset)]
+    }
+
+    pub fn write_byte(&mut self, segment: u16, offset: u16, value: u8) {
+        self.bytes[Memory::physical_address(segment, offset)] = value;
+    }
+
+    // little endian, a word at offset 0xffff takes its high byte from offset 0 of the same segment
+    pub fn read_word(&self, segment: u16, offset: u16) -> u16 {
+        u16::from_le_bytes([
+            self.read_byte(segment, offset),
+            self.read_byte(segment, offset.wrapping_add(1)),
+        ])
+    }
+
+    pub fn write_word(&mut self, segment: u16, offset: u16, value: u16) {
+        let [low, high] = value.to_le_bytes();
+        self.write_byte(segment, offset, low);
+        self.write_byte(segment, offset.wrapping_add(1), high);
+    }
+
+    // copies image to segment:offset, returns the physical addresses it went to
+    // past 0xfffff it carries on at 0, the range then ends past MEMORY_SIZE
+    pub fn load(&mut self, image: &[u8], segment: u16, offset: u16) -> std::ops::Range<usize> {
+        let start = Memory::physical_address(segment, offset);
+        for (i, byte) in image.iter().enumerate() {
+            self.bytes[(start + i) & (MEMORY_SIZE - 1)] = *byte;
+        }
+        start..start + image.len()
+    }
+}
@@ -0,0 +1,478 @@
+// every first byte of an 8086 instruction, and the reg field selected groups behind some of them
+use crate::instruction_decode::Opcode::{self, *};
+use OperandSpec::*;
+
+// how one operand is encoded, operands are listed destination first so the d bit is in their order
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OperandSpec {
+    // mod r/m, a memory operand takes its size from the other operand
+    Rm,
+    // mod r/m, a memory operand spells out byte or word
+    SizedRm,
+    // mod r/m, a segment:offset pointer in memory
+    FarRm,
+    // mod r/m that has to be memory, lea, lds and les take its address
+    Mem,
+    // general register in the reg field
+    Reg,
+    // segment register in the reg field, always a word
+    Segment,
+    // word register in the low three bits of the opcode
+    OpcodeReg,
+    // segment register in bits 3-4 of the opcode
+    OpcodeSegment,
+    // al or ax
+    Accumulator,
+    // byte or word
+    Immediate,
+    // byte or word, a byte shown signed
+    SignedImmediate,
+    // single byte sign extended to a word
+    SignExtended,
+    // single byte whatever the width, an interrupt vector, port or aam/aad base
+    ImmediateByte,
+    // a word whatever the width, bytes ret pops
+    ImmediateWord,
+    // 16 bit address following the opcode
+    Direct,
+    // 8 bit displacement from the next instruction
+    Short,
+    // 16 bit displacement from the next instruction
+    Near,
+    // offset then segment
+    Far,
+    // opcode bits 0-2 and the reg field make the coprocessor opcode
+    Escape,
+    // count of one for shifts and rotates
+    One,
+    CL,
+    DX,
+    AX,
+}
+
+pub type Group = [Option<(Opcode, &'static [OperandSpec])>; 8];
+
+#[derive(Clone, Copy, Debug)]
+pub enum Mnemonic {
+    Op(Opcode),
+    // the reg field of the mod r/m byte picks the opcode and operands
+    Group(&'static Group),
+    // segment override, lock, rep and repne
+    Prefix,
+    Invalid,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct OpcodeEntry {
+    pub mnemonic: Mnemonic,
+    // the w bit, 1 for word operands
+    pub w: u8,
+    pub operands: &'static [OperandSpec],
+}
+
+impl OpcodeEntry {
+    // the operands are read from a mod r/m byte following the opcode
+    pub fn has_mod_rm(&self) -> bool {
+        matches!(self.mnemonic, Mnemonic::Group(_))
+            || self
+                .operands
+                .iter()
+                .any(|spec| matches!(spec, Rm | SizedRm | FarRm | Mem | Reg | Segment | Escape))
+    }
+}
+
+const fn op(opcode: Opcode, w: u8, operands: &'static [OperandSpec]) -> OpcodeEntry {
+    OpcodeEntry {
+        mnemonic: Mnemonic::Op(opcode),
+        w,
+        operands,
+    }
+}
+
+const fn group(table: &'static Group, w: u8) -> OpcodeEntry {
+    OpcodeEntry {
+        mnemonic: Mnemonic::Group(table),
+        w,
+        operands: &[],
+    }
+}
+
+const PREFIX: OpcodeEntry = OpcodeEntry {
+    mnemonic: Mnemonic::Prefix,
+    w: 0,
+    operands: &[],
+};
+
+const INVALID: OpcodeEntry = OpcodeEntry {
+    mnemonic: Mnemonic::Invalid,
+    w: 0,
+    operands: &[],
+};
+
+// 100000sw, immediate to register/memory, the reg field picks the operation
+const GROUP_IMMEDIATE: Group = [
+    Some((ADD, &[SizedRm, Immediate])),
+    Some((OR, &[SizedRm, Immediate])),
+    Some((ADC, &[SizedRm, Immediate])),
+    Some((SBB, &[SizedRm, Immediate])),
+    Some((AND, &[SizedRm, Immediate])),
+    Some((SUB, &[SizedRm, Immediate])),
+    Some((XOR, &[SizedRm, Immediate])),
+    Some((CMP, &[SizedRm, Immediate])),
+];
+
+const GROUP_SIGN_EXTENDED: Group = [
+    Some((ADD, &[SizedRm, SignExtended])),
+    Some((OR, &[SizedRm, SignExtended])),
+    Some((ADC, &[SizedRm, SignExtended])),
+    Some((SBB, &[SizedRm, SignExtended])),
+    Some((AND, &[SizedRm, SignExtended])),
+    Some((SUB, &[SizedRm, SignExtended])),
+    Some((XOR, &[SizedRm, SignExtended])),
+    Some((CMP, &[SizedRm, SignExtended])),
+];
+
+const GROUP_POP: Group = [
+    Some((POP, &[SizedRm])),
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+];
+
+const GROUP_MOV: Group = [
+    Some((MOV, &[SizedRm, Immediate])),
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+];
+
+// 110100vw, v = 1 counts by cl
+const GROUP_SHIFT_ONE: Group = [
+    Some((ROL, &[SizedRm, One])),
+    Some((ROR, &[SizedRm, One])),
+    Some((RCL, &[SizedRm, One])),
+    Some((RCR, &[SizedRm, One])),
+    Some((SHL, &[SizedRm, One])),
+    Some((SHR, &[SizedRm, One])),
+    None,
+    Some((SAR, &[SizedRm, One])),
+];
+
+const GROUP_SHIFT_CL: Group = [
+    Some((ROL, &[SizedRm, CL])),
+    Some((ROR, &[SizedRm, CL])),
+    Some((RCL, &[SizedRm, CL])),
+    Some((RCR, &[SizedRm, CL])),
+    Some((SHL, &[SizedRm, CL])),
+    Some((SHR, &[SizedRm, CL])),
+    None,
+    Some((SAR, &[SizedRm, CL])),
+];
+
+const GROUP_UNARY: Group = [
+    Some((TEST, &[SizedRm, Immediate])),
+    None,
+    Some((NOT, &[SizedRm])),
+    Some((NEG, &[SizedRm])),
+    Some((MUL, &[SizedRm])),
+    Some((IMUL, &[SizedRm])),
+    Some((DIV, &[SizedRm])),
+    Some((IDIV, &[SizedRm])),
+];
+
+const GROUP_INC_DEC: Group = [
+    Some((INC, &[SizedRm])),
+    Some((DEC, &[SizedRm])),
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+];
+
+const GROUP_INDIRECT: Group = [
+    Some((INC, &[SizedRm])),
+    Some((DEC, &[SizedRm])),
+    Some((CALL, &[SizedRm])),
+    Some((CALL, &[FarRm])),
+    Some((JMP, &[SizedRm])),
+    Some((JMP, &[FarRm])),
+    Some((PUSH, &[SizedRm])),
+    None,
+];
+
+#[rustfmt::skip]
+pub const OPCODE_TABLE: [OpcodeEntry; 256] = [
+    /* 00 */ op(ADD, 0, &[Rm, Reg]),
+    /* 01 */ op(ADD, 1, &[Rm, Reg]),
+    /* 02 */ op(ADD, 0, &[Reg, Rm]),
+    /* 03 */ op(ADD, 1, &[Reg, Rm]),
+    /* 04 */ op(ADD, 0, &[Accumulator, SignedImmediate]),
+    /* 05 */ op(ADD, 1, &[Accumulator, SignedImmediate]),
+    /* 06 */ op(PUSH, 1, &[OpcodeSegment]),
+    /* 07 */ op(POP, 1, &[OpcodeSegment]),
+    /* 08 */ op(OR, 0, &[Rm, Reg]),
+    /* 09 */ op(OR, 1, &[Rm, Reg]),
+    /* 0A */ op(OR, 0, &[Reg, Rm]),
+    /* 0B */ op(OR, 1, &[Reg, Rm]),
+    /* 0C */ op(OR, 0, &[Accumulator, SignedImmediate]),
+    /* 0D */ op(OR, 1, &[Accumulator, SignedImmediate]),
+    /* 0E */ op(PUSH, 1, &[OpcodeSegment]),
+    // pop cs is not a usable instruction
+    /* 0F */ INVALID,
+    /* 10 */ op(ADC, 0, &[Rm, Reg]),
+    /* 11 */ op(ADC, 1, &[Rm, Reg]),
+    /* 12 */ op(ADC, 0, &[Reg, Rm]),
+    /* 13 */ op(ADC, 1, &[Reg, Rm]),
+    /* 14 */ op(ADC, 0, &[Accumulator, SignedImmediate]),
+    /* 15 */ op(ADC, 1, &[Accumulator, SignedImmediate]),
+    /* 16 */ op(PUSH, 1, &[OpcodeSegment]),
+    /* 17 */ op(POP, 1, &[OpcodeSegment]),
+    /* 18 */ op(SBB, 0, &[Rm, Reg]),
+    /* 19 */ op(SBB, 1, &[Rm, Reg]),
+    /* 1A */ op(SBB, 0, &[Reg, Rm]),
+    /* 1B */ op(SBB, 1, &[Reg, Rm]),
+    /* 1C */ op(SBB, 0, &[Accumulator, SignedImmediate]),
+    /* 1D */ op(SBB, 1, &[Accumulator, SignedImmediate]),
+    /* 1E */ op(PUSH, 1, &[OpcodeSegment]),
+    /* 1F */ op(POP, 1, &[OpcodeSegment]),
+    /* 20 */ op(AND, 0, &[Rm, Reg]),
+    /* 21 */ op(AND, 1, &[Rm, Reg]),
+    /* 22 */ op(AND, 0, &[Reg, Rm]),
+    /* 23 */ op(AND, 1, &[Reg, Rm]),
+    /* 24 */ op(AND, 0, &[Accumulator, SignedImmediate]),
+    /* 25 */ op(AND, 1, &[Accumulator, SignedImmediate]),
+    /* 26 */ PREFIX,
+    /* 27 */ op(DAA, 0, &[]),
+    /* 28 */ op(SUB, 0, &[Rm, Reg]),
+    /* 29 */ op(SUB, 1, &[Rm, Reg]),
+    /* 2A */ op(SUB, 0, &[Reg, Rm]),
+    /* 2B */ op(SUB, 1, &[Reg, Rm]),
+    /* 2C */ op(SUB, 0, &[Accumulator, SignedImmediate]),
+    /* 2D */ op(SUB, 1, &[Accumulator, SignedImmediate]),
+    /* 2E */ PREFIX,
+    /* 2F */ op(DAS, 0, &[]),
+    /* 30 */ op(XOR, 0, &[Rm, Reg]),
+    /* 31 */ op(XOR, 1, &[Rm, Reg]),
+    /* 32 */ op(XOR, 0, &[Reg, Rm]),
+    /* 33 */ op(XOR, 1, &[Reg, Rm]),
+    /* 34 */ op(XOR, 0, &[Accumulator, SignedImmediate]),
+    /* 35 */ op(XOR, 1, &[Accumulator, SignedImmediate]),
+    /* 36 */ PREFIX,
+    /* 37 */ op(AAA, 0, &[]),
+    /* 38 */ op(CMP, 0, &[Rm, Reg]),
+    /* 39 */ op(CMP, 1, &[Rm, Reg]),
+    /* 3A */ op(CMP, 0, &[Reg, Rm]),
+    /* 3B */ op(CMP, 1, &[Reg, Rm]),
+    /* 3C */ op(CMP, 0, &[Accumulator, SignedImmediate]),
+    /* 3D */ op(CMP, 1, &[Accumulator, SignedImmediate]),
+    /* 3E */ PREFIX,
+    /* 3F */ op(AAS, 0, &[]),
+    /* 40 */ op(INC, 1, &[OpcodeReg]),
+    /* 41 */ op(INC, 1, &[OpcodeReg]),
+    /* 42 */ op(INC, 1, &[OpcodeReg]),
+    /* 43 */ op(INC, 1, &[OpcodeReg]),
+    /* 44 */ op(INC, 1, &[OpcodeReg]),
+    /* 45 */ op(INC, 1, &[OpcodeReg]),
+    /* 46 */ op(INC, 1, &[OpcodeReg]),
+    /* 47 */ op(INC, 1, &[OpcodeReg]),
+    /* 48 */ op(DEC, 1, &[OpcodeReg]),
+    /* 49 */ op(DEC, 1, &[OpcodeReg]),
+    /* 4A */ op(DEC, 1, &[OpcodeReg]),
+    /* 4B */ op(DEC, 1, &[OpcodeReg]),
+    /* 4C */ op(DEC, 1, &[OpcodeReg]),
+    /* 4D */ op(DEC, 1, &[OpcodeReg]),
+    /* 4E */ op(DEC, 1, &[OpcodeReg]),
+    /* 4F */ op(DEC, 1, &[OpcodeReg]),
+    /* 50 */ op(PUSH, 1, &[OpcodeReg]),
+    /* 51 */ op(PUSH, 1, &[OpcodeReg]),
+    /* 52 */ op(PUSH, 1, &[OpcodeReg]),
+    /* 53 */ op(PUSH, 1, &[OpcodeReg]),
+    /* 54 */ op(PUSH, 1, &[OpcodeReg]),
+    /* 55 */ op(PUSH, 1, &[OpcodeReg]),
+    /* 56 */ op(PUSH, 1, &[OpcodeReg]),
+    /* 57 */ op(PUSH, 1, &[OpcodeReg]),
+    /* 58 */ op(POP, 1, &[OpcodeReg]),
+    /* 59 */ op(POP, 1, &[OpcodeReg]),
+    /* 5A */ op(POP, 1, &[OpcodeReg]),
+    /* 5B */ op(POP, 1, &[OpcodeReg]),
+    /* 5C */ op(POP, 1, &[OpcodeReg]),
+    /* 5D */ op(POP, 1, &[OpcodeReg]),
+    /* 5E */ op(POP, 1, &[OpcodeReg]),
+    /* 5F */ op(POP, 1, &[OpcodeReg]),
+    // undocumented, the 8086 mirrors the jumps at 70-7f here
+    /* 60 */ INVALID,
+    /* 61 */ INVALID,
+    /* 62 */ INVALID,
+    /* 63 */ INVALID,
+    /* 64 */ INVALID,
+    /* 65 */ INVALID,
+    /* 66 */ INVALID,
+    /* 67 */ INVALID,
+    /* 68 */ INVALID,
+    /* 69 */ INVALID,
+    /* 6A */ INVALID,
+    /* 6B */ INVALID,
+    /* 6C */ INVALID,
+    /* 6D */ INVALID,
+    /* 6E */ INVALID,
+    /* 6F */ INVALID,
+    /* 70 */ op(JO, 0, &[Short]),
+    /* 71 */ op(JNO, 0, &[Short]),
+    /* 72 */ op(JB, 0, &[Short]),
+    /* 73 */ op(JNB, 0, &[Short]),
+    /* 74 */ op(JE, 0, &[Short]),
+    /* 75 */ op(JNE, 0, &[Short]),
+    /* 76 */ op(JBE, 0, &[Short]),
+    /* 77 */ op(JA, 0, &[Short]),
+    /* 78 */ op(JS, 0, &[Short]),
+    /* 79 */ op(JNS, 0, &[Short]),
+    /* 7A */ op(JP, 0, &[Short]),
+    /* 7B */ op(JNP, 0, &[Short]),
+    /* 7C */ op(JL, 0, &[Short]),
+    /* 7D */ op(JNL, 0, &[Short]),
+    /* 7E */ op(JLE, 0, &[Short]),
+    /* 7F */ op(JG, 0, &[Short]),
+    /* 80 */ group(&GROUP_IMMEDIATE, 0),
+    /* 81 */ group(&GROUP_IMMEDIATE, 1),
+    /* 82 */ group(&GROUP_IMMEDIATE, 0),
+    /* 83 */ group(&GROUP_SIGN_EXTENDED, 1),
+    /* 84 */ op(TEST, 0, &[Rm, Reg]),
+    /* 85 */ op(TEST, 1, &[Rm, Reg]),
+    /* 86 */ op(XCHG, 0, &[Reg, Rm]),
+    /* 87 */ op(XCHG, 1, &[Reg, Rm]),
+    /* 88 */ op(MOV, 0, &[Rm, Reg]),
+    /* 89 */ op(MOV, 1, &[Rm, Reg]),
+    /* 8A */ op(MOV, 0, &[Reg, Rm]),
+    /* 8B */ op(MOV, 1, &[Reg, Rm]),
+    /* 8C */ op(MOV, 1, &[Rm, Segment]),
+    /* 8D */ op(LEA, 1, &[Reg, Mem]),
+    /* 8E */ op(MOV, 1, &[Segment, Rm]),
+    /* 8F */ group(&GROUP_POP, 1),
+    // xchg ax, ax
+    /* 90 */ op(NOP, 0, &[]),
+    /* 91 */ op(XCHG, 1, &[AX, OpcodeReg]),
+    /* 92 */ op(XCHG, 1, &[AX, OpcodeReg]),
+    /* 93 */ op(XCHG, 1, &[AX, OpcodeReg]),
+    /* 94 */ op(XCHG, 1, &[AX, OpcodeReg]),
+    /* 95 */ op(XCHG, 1, &[AX, OpcodeReg]),
+    /* 96 */ op(XCHG, 1, &[AX, OpcodeReg]),
+    /* 97 */ op(XCHG, 1, &[AX, OpcodeReg]),
+    /* 98 */ op(CBW, 0, &[]),
+    /* 99 */ op(CWD, 1, &[]),
+    /* 9A */ op(CALL, 1, &[Far]),
+    /* 9B */ op(WAIT, 0, &[]),
+    /* 9C */ op(PUSHF, 1, &[]),
+    /* 9D */ op(POPF, 1, &[]),
+    /* 9E */ op(SAHF, 0, &[]),
+    /* 9F */ op(LAHF, 0, &[]),
+    /* A0 */ op(MOV, 0, &[Accumulator, Direct]),
+    /* A1 */ op(MOV, 1, &[Accumulator, Direct]),
+    /* A2 */ op(MOV, 0, &[Direct, Accumulator]),
+    /* A3 */ op(MOV, 1, &[Direct, Accumulator]),
+    /* A4 */ op(MOVSB, 0, &[]),
+    /* A5 */ op(MOVSW, 1, &[]),
+    /* A6 */ op(CMPSB, 0, &[]),
+    /* A7 */ op(CMPSW, 1, &[]),
+    /* A8 */ op(TEST, 0, &[Accumulator, Immediate]),
+    /* A9 */ op(TEST, 1, &[Accumulator, Immediate]),
+    /* AA */ op(STOSB, 0, &[]),
+    /* AB */ op(STOSW, 1, &[]),
+    /* AC */ op(LODSB, 0, &[]),
+    /* AD */ op(LODSW, 1, &[]),
+    /* AE */ op(SCASB, 0, &[]),
+    /* AF */ op(SCASW, 1, &[]),
+    /* B0 */ op(MOV, 0, &[OpcodeReg, Immediate]),
+    /* B1 */ op(MOV, 0, &[OpcodeReg, Immediate]),
+    /* B2 */ op(MOV, 0, &[OpcodeReg, Immediate]),
+    /* B3 */ op(MOV, 0, &[OpcodeReg, Immediate]),
+    /* B4 */ op(MOV, 0, &[OpcodeReg, Immediate]),
+    /* B5 */ op(MOV, 0, &[OpcodeReg, Immediate]),
+    /* B6 */ op(MOV, 0, &[OpcodeReg, Immediate]),
+    /* B7 */ op(MOV, 0, &[OpcodeReg, Immediate]),
+    /* B8 */ op(MOV, 1, &[OpcodeReg, Immediate]),
+    /* B9 */ op(MOV, 1, &[OpcodeReg, Immediate]),
+    /* BA */ op(MOV, 1, &[OpcodeReg, Immediate]),
+    /* BB */ op(MOV, 1, &[OpcodeReg, Immediate]),
+    /* BC */ op(MOV, 1, &[OpcodeReg, Immediate]),
+    /* BD */ op(MOV, 1, &[OpcodeReg, Immediate]),
+    /* BE */ op(MOV, 1, &[OpcodeReg, Immediate]),
+    /* BF */ op(MOV, 1, &[OpcodeReg, Immediate]),
+    // undocumented, the 8086 mirrors ret and retf at c2/c3 and ca/cb here
+    /* C0 */ INVALID,
+    /* C1 */ INVALID,
+    /* C2 */ op(RET, 1, &[ImmediateWord]),
+    /* C3 */ op(RET, 1, &[]),
+    /* C4 */ op(LES, 1, &[Reg, Mem]),
+    /* C5 */ op(LDS, 1, &[Reg, Mem]),
+    /* C6 */ group(&GROUP_MOV, 0),
+    /* C7 */ group(&GROUP_MOV, 1),
+    /* C8 */ INVALID,
+    /* C9 */ INVALID,
+    /* CA */ op(RETF, 1, &[ImmediateWord]),
+    /* CB */ op(RETF, 1, &[]),
+    /* CC */ op(INT3, 0, &[]),
+    /* CD */ op(INT, 0, &[ImmediateByte]),
+    /* CE */ op(INTO, 0, &[]),
+    /* CF */ op(IRET, 1, &[]),
+    /* D0 */ group(&GROUP_SHIFT_ONE, 0),
+    /* D1 */ group(&GROUP_SHIFT_ONE, 1),
+    /* D2 */ group(&GROUP_SHIFT_CL, 0),
+    /* D3 */ group(&GROUP_SHIFT_CL, 1),
+    // the second byte is the base, nasm's plain aam/aad is base 10
+    /* D4 */ op(AAM, 0, &[ImmediateByte]),
+    /* D5 */ op(AAD, 0, &[ImmediateByte]),
+    // undocumented salc
+    /* D6 */ INVALID,
+    /* D7 */ op(XLAT, 0, &[]),
+    /* D8 */ op(ESC, 1, &[Escape, Rm]),
+    /* D9 */ op(ESC, 1, &[Escape, Rm]),
+    /* DA */ op(ESC, 1, &[Escape, Rm]),
+    /* DB */ op(ESC, 1, &[Escape, Rm]),
+    /* DC */ op(ESC, 1, &[Escape, Rm]),
+    /* DD */ op(ESC, 1, &[Escape, Rm]),
+    /* DE */ op(ESC, 1, &[Escape, Rm]),
+    /* DF */ op(ESC, 1, &[Escape, Rm]),
+    /* E0 */ op(LOOPNZ, 0, &[Short]),
+    /* E1 */ op(LOOPZ, 0, &[Short]),
+    /* E2 */ op(LOOP, 0, &[Short]),
+    /* E3 */ op(JCXZ, 0, &[Short]),
+    /* E4 */ op(IN, 0, &[Accumulator, ImmediateByte]),
+    /* E5 */ op(IN, 1, &[Accumulator, ImmediateByte]),
+    /* E6 */ op(OUT, 0, &[ImmediateByte, Accumulator]),
+    /* E7 */ op(OUT, 1, &[ImmediateByte, Accumulator]),
+    /* E8 */ op(CALL, 1, &[Near]),
+    /* E9 */ op(JMP, 1, &[Near]),
+    /* EA */ op(JMP, 1, &[Far]),
+    /* EB */ op(JMP, 0, &[Short]),
+    /* EC */ op(IN, 0, &[Accumulator, DX]),
+    /* ED */ op(IN, 1, &[Accumulator, DX]),
+    /* EE */ op(OUT, 0, &[DX, Accumulator]),
+    /* EF */ op(OUT, 1, &[DX, Accumulator]),
+    /* F0 */ PREFIX,
+    // undocumented, the 8086 treats it as lock
+    /* F1 */ INVALID,
+    /* F2 */ PREFIX,
+    /* F3 */ PREFIX,
+    /* F4 */ op(HLT, 0, &[]),
+    /* F5 */ op(CMC, 0, &[]),
+    /* F6 */ group(&GROUP_UNARY, 0),
+    /* F7 */ group(&GROUP_UNARY, 1),
+    /* F8 */ op(CLC, 0, &[]),
+    /* F9 */ op(STC, 0, &[]),
+    /* FA */ op(CLI, 0, &[]),
+    /* FB */ op(STI, 0, &[]),
+    /* FC */ op(CLD, 0, &[]),
+    /* FD */ op(STD, 0, &[]),
+    /* FE */ group(&GROUP_INC_DEC, 0),
+    /* FF */ group(&GROUP_INDIRECT, 1),
+];